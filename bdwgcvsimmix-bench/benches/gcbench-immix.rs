@@ -2,25 +2,13 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use libimmixcons::{object::*, *};
 use threading::{immix_mutator_yieldpoint, immix_register_thread};
+#[derive(HeapObject)]
 pub struct Node {
     left: Option<Gc<Self>>,
     right: Option<Gc<Self>>,
     i: i32,
     j: i32,
 }
-impl HeapObject for Node {
-    const RTTI: GCRTTI = make_rtti_for!(Node);
-    fn visit_references(&mut self, tracer: &mut dyn Tracer) {
-        match self.left {
-            Some(ref mut left) => left.visit_references(tracer),
-            _ => (),
-        }
-        match self.right {
-            Some(ref mut right) => right.visit_references(tracer),
-            _ => (),
-        }
-    }
-}
 fn TreeSize(i: i32) -> i32 {
     (1 << (i + 1)) - 1
 }
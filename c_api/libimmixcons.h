@@ -44,6 +44,10 @@
 #define GC_STATS_SUMMARY 1
 #define GC_STATS_VERBOSE 2
 
+#define IMMIX_ROOT_STACK 0
+#define IMMIX_ROOT_CALLBACK 1
+#define IMMIX_ROOT_STATIC 2
+
 void immix_enable_stats(int state);
 void immix_dump_summary(void);
 
@@ -82,10 +86,35 @@ typedef struct GCRTTI {
    * Object finalizer. Invoked when object is dead.
    */
   void (*finalizer)(uint8_t*);
+  /**
+   * If set to true object body is scanned word by word for conservative references in addition to `visit_references`.
+   * Objects found this way are pinned and evacuation is disabled while such objects are alive.
+   */
+  bool conservative;
+  /**
+   * Alignment of object value. If it is above 8 the value is placed at the first aligned address after the header
+   * and one word that holds distance from the header to the value, evacuation keeps the alignment. Zero means
+   * default alignment of 8.
+   */
+  uintptr_t alignment;
+  /**
+   * If set to true moves of objects that use this RTTI are reported to callbacks registered with
+   * `immix_register_relocation_callback`.
+   */
+  bool notify_relocation;
 } GCRTTI;
 
+/**
+ * Define IMMIX_HEADER_WORD when library is built with `header-word` feature.
+ */
 typedef struct GCObject {
   uint64_t rtti;
+#ifdef IMMIX_HEADER_WORD
+  /**
+   * Header word reserved for users of the collector. Use `immix_header_word_*` functions to access it.
+   */
+  uint64_t word;
+#endif
 } GCObject;
 
 /**
@@ -95,10 +124,28 @@ typedef struct TaggedPointer_usize {
   uint64_t raw;
 } TaggedPointer_usize;
 
+/**
+ * Object moved from `old` to `new` address.
+ */
+typedef struct Relocation {
+  struct GCObject *old;
+  struct GCObject *new_;
+} Relocation;
+
+/**
+ * Callback that receives `count` relocations that happened during GC cycle.
+ */
+typedef void (*RelocationCallback)(uint8_t *data, const struct Relocation *relocations, uintptr_t count);
+
 typedef struct RawGc {
   struct TaggedPointer_usize vtable;
+#ifdef IMMIX_HEADER_WORD
+  uint64_t word;
+#endif
 } RawGc;
 
+typedef void (*HeapWalkCallback)(uint8_t *data, struct GCObject *object, const struct GCRTTI *rtti, uintptr_t size);
+
 
 void immix_noop1(void* word);
 /* Explicitly tell the collector that an object is reachable    */
@@ -120,6 +167,180 @@ void immix_noop1(void* word);
  */
 void immix_register_ongc_callback(CollectRootsCallback callback, uint8_t *data);
 
+/**
+ * Register callback that will be invoked after each GC cycle that moved objects with `notify_relocation` set in
+ * their RTTI. All moves of the cycle are passed at once, callbacks are invoked before mutators are resumed and must
+ * not allocate in GC heap.
+ *
+ *
+ * WARNING: There is no way to "unregister" this callback.
+ */
+void immix_register_relocation_callback(RelocationCallback callback, uint8_t *data);
+
+/**
+ * Enable scanning of static data segments and thread-local storage for conservative roots.
+ *
+ * Should be invoked before `immix_init` and `immix_register_thread`. If GC is already initialized
+ * data segments and TLS block of the current thread are registered immediately.
+ */
+void immix_enable_static_roots(void);
+
+/**
+ * Add memory region from `begin` to `end` that is scanned for conservative roots at each GC cycle until
+ * it is removed with `immix_remove_roots`.
+ */
+void immix_add_roots(uintptr_t begin, uintptr_t end);
+
+/**
+ * Remove all regions added by `immix_add_roots` that are entirely within `begin` and `end`.
+ */
+void immix_remove_roots(uintptr_t begin, uintptr_t end);
+
+/**
+ * Set bits that are stripped from words found by conservative root scanning before they are checked for
+ * pointers into the heap. Use this when VM stores tagged pointers on stack (e.g low-bit tags).
+ */
+void immix_set_conservative_tag_mask(uintptr_t tag_mask);
+
+/**
+ * Enable or disable heap verification before and after each GC cycle. Verification is slow and should be used only
+ * for debugging `visit_references` implementations.
+ */
+void immix_enable_verification(bool enable);
+
+/**
+ * Invoke `callback` on every allocated object with its RTTI and size. Other threads are stopped while heap is
+ * walked, `callback` must not allocate or trigger GC.
+ */
+void immix_walk_heap(HeapWalkCallback callback, uint8_t *data);
+
+/**
+ * Stop the world and write snapshot of the heap to file at `path`. The format is described in `src/snapshot.rs`:
+ * `IMMIXDUMP 1` header followed by `O <object> <size> <rtti> <name>` object lines, `E <from> <slot> <to>` edge
+ * lines and `R <stack|callback|static> <slot> <object>` root lines with hexadecimal numbers. VM handles are
+ * reported by the roots callback and are labelled `callback`.
+ *
+ * # Safety
+ * `path` must point to a null terminated string.
+ *
+ * ## Return value
+ * Returns false if file could not be written.
+ */
+bool immix_dump_heap(const char *path);
+
+/**
+ * Find chain of objects that keeps `object` alive. Conservatively found stack references to `object` itself are
+ * used only if no other path reaches it since the caller always has one, `object` is then reported as referenced
+ * directly from the stack.
+ *
+ * ## Inputs
+ * - `object`: Object to query.
+ * - `path`: Buffer that receives objects from the root to `object`. Might be null if `capacity` is zero.
+ * - `capacity`: Size of `path` buffer, objects that do not fit are not written.
+ * - `source`: Receives kind of the root (see `IMMIX_ROOT_*` constants). Might be null.
+ *
+ * ## Return value
+ * Returns length of the chain or zero if `object` is not reachable from roots.
+ *
+ * # Safety
+ * `path` must be valid for writes of `capacity` pointers and `source` must be null or valid for writes.
+ */
+uintptr_t immix_find_retention_path(struct GCObject *object,
+                                    struct GCObject **path,
+                                    uintptr_t capacity,
+                                    int *source);
+
+/**
+ * Print chain of objects that keeps `object` alive with RTTI names of the objects. Meant to be called from a
+ * debugger. `object` is only looked up in the heap and printed, so any pointer can be passed.
+ */
+void immix_print_retention_path(struct GCObject *object);
+
+/**
+ * Set sampling interval in bytes. Allocation is sampled every time another `interval` bytes are allocated.
+ * Zero disables sampling, already recorded samples are kept.
+ */
+void immix_set_sampling_interval(uintptr_t interval);
+
+/**
+ * Drop all recorded allocation samples.
+ */
+void immix_clear_allocation_samples(void);
+
+/**
+ * Write recorded samples to file at `path` as folded stacks. Each line contains frames separated by `;` (the
+ * outermost first, RTTI of allocated object last) and number of allocated bytes.
+ *
+ * ## Return value
+ * Returns false if file could not be written.
+ *
+ * # Safety
+ * `path` must point to a null terminated string.
+ */
+bool immix_write_folded_profile(const char *path);
+
+/**
+ * Write recorded samples to file at `path` as uncompressed pprof profile. `go tool pprof` reads such files directly.
+ *
+ * ## Return value
+ * Returns false if file could not be written.
+ *
+ * # Safety
+ * `path` must point to a null terminated string.
+ */
+bool immix_write_pprof_profile(const char *path);
+
+/**
+ * Return number of bytes in live large objects. Objects that died since the last GC cycle are counted too.
+ */
+uintptr_t immix_large_object_live_bytes(void);
+
+/**
+ * Return total number of bytes freed in medium object space.
+ */
+uintptr_t immix_medium_object_freed_bytes(void);
+
+/**
+ * Return total number of bytes freed in large object space.
+ */
+uintptr_t immix_large_object_freed_bytes(void);
+
+/**
+ * Return number of bytes in heap lines that are blacklisted because false conservative pointers point into them.
+ */
+uintptr_t immix_blacklisted_bytes(void);
+
+/**
+ * Register code range from `begin` to `end` that contains JIT-compiled code with stack maps.
+ */
+void immix_register_code_range(uintptr_t begin, uintptr_t end);
+
+/**
+ * Unregister code range from `begin` to `end` and remove all of its stack maps. Must be invoked before
+ * JIT-compiled code is freed.
+ */
+void immix_unregister_code_range(uintptr_t begin, uintptr_t end);
+
+/**
+ * Add stack map for safepoint at `return_address`.
+ *
+ * ## Inputs
+ * - `return_address`: Return address of the call at safepoint, must be inside of registered code range.
+ * - `frame_size`: Size of the frame in bytes starting at frame base (address right above the return address slot).
+ * - `slots`: Offsets of slots that hold GC references relative to frame base. Slots might be null.
+ * - `count`: Number of offsets in `slots`.
+ *
+ * ## Return value
+ * Returns false if `return_address` is not inside of registered code range.
+ *
+ * # Safety
+ * `slots` must be valid for reads of `count` offsets.
+ */
+bool immix_add_stack_map(uintptr_t return_address,
+                         uintptr_t frame_size,
+                         const intptr_t *slots,
+                         uintptr_t count);
+
 /**
  * no-op callback. This is used in place of `CollectRootsCallback` internally
  */
@@ -151,7 +372,8 @@ void immix_init_logger(void);
 
 /**
  * Allocate memory of `size + sizeof(GCObject)` bytes in Immix heap and set object RTTI to `rtti`. If `size` >= 8KB then
- * object is allocated inside large object space.
+ * object is allocated inside medium object space, objects above 256KB and objects that do not fit into exhausted
+ * medium object space are allocated inside large object space.
  *
  *
  * ## Return value
@@ -161,6 +383,30 @@ void immix_init_logger(void);
 struct GCObject *immix_alloc(uintptr_t size,
                              struct GCRTTI *rtti);
 
+/**
+ * Allocate memory for object that does not contain any references into GC heap. Such objects are
+ * never scanned: GC marks them without invoking `visit_references` of `rtti`.
+ *
+ * ## Return value
+ * Returns pointer to allocated memory or null if allocation failed after emergency GC cycle.
+ */
+struct GCObject *immix_alloc_atomic(uintptr_t size,
+                                    struct GCRTTI *rtti);
+
+/**
+ * Allocate object like `immix_alloc` but place its value at address aligned to `align`. If `align` is above 8 the
+ * value is placed at the first aligned address after the header and one word that holds distance from the header
+ * to the value, so `heap_size` of `rtti` must return this distance plus size of value. `rtti->alignment` must be
+ * equal to `align` so evacuation keeps the alignment.
+ *
+ * ## Return value
+ * Returns pointer to allocated memory or null if allocation failed after emergency GC cycle or `align` is not a
+ * power of two or is larger than 4096.
+ */
+struct GCObject *immix_alloc_aligned(uintptr_t size,
+                                     uintptr_t align,
+                                     const struct GCRTTI *rtti);
+
 /**
  * Trigger garbage collection. If `move_objects` is true might potentially move unpinned objects.
  *
@@ -173,6 +419,16 @@ void immix_collect(bool move_objects);
 
 void tracer_trace(struct TracerPtr p, struct RawGc **gc_val);
 
+/**
+ * Trace tagged reference stored in `slot`. Bits of `tag_mask` are stripped before tracing and
+ * reapplied to the forwarded pointer after evacuation. `slot` must hold a tagged pointer or a tagged null,
+ * values that are not pointers must be skipped by the caller.
+ *
+ * # Safety
+ * `slot` must be valid for reads and writes.
+ */
+void tracer_trace_tagged(struct TracerPtr p, uintptr_t *slot, uintptr_t tag_mask);
+
 /**
  * Add memory region from `begin` to `end` for scanning for heap objects.
  */
@@ -230,12 +486,51 @@ int8_t immix_unsafe_leave(int8_t state);
  * Returns current state to restore later.
  */
 int8_t immix_safe_enter(void);
+/**
+ * Identity hash of `object` that does not change when object is moved by GC.
+ */
+uintptr_t immix_identity_hash(struct GCObject *object);
+
 const GCRTTI* immix_object_get_rtti(GCObject*);
 
+#ifdef IMMIX_HEADER_WORD
+/**
+ * Load header word of `object`.
+ */
+uintptr_t immix_header_word_load(const struct GCObject *object);
+
+/**
+ * Store `value` to header word of `object`.
+ */
+void immix_header_word_store(const struct GCObject *object, uintptr_t value);
+
+/**
+ * Replace header word of `object` with `new` if it is equal to `current`. Returns previous value, the exchange
+ * succeeded if it is equal to `current`.
+ */
+uintptr_t immix_header_word_compare_exchange(const struct GCObject *object, uintptr_t current, uintptr_t new_);
+
+/**
+ * Set bits of `bits` in header word of `object`. Returns previous value.
+ */
+uintptr_t immix_header_word_fetch_or(const struct GCObject *object, uintptr_t bits);
+
+/**
+ * Clear bits that are not set in `mask` in header word of `object`. Returns previous value.
+ */
+uintptr_t immix_header_word_fetch_and(const struct GCObject *object, uintptr_t mask);
+#endif
+
 /**
  * Leave safe for GC state and restore previous state from `state` argument.
  */
 int8_t immix_safe_leave(int8_t state);
 
+/**
+ * Write barrier. Call it before storing a reference into `slot` of a heap object.
+ * Currently it is a no-op, but collectors that track heap writes will rely on it.
+ */
+void immix_write_barrier(const void* slot);
+
 
 #endif
\ No newline at end of file
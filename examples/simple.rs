@@ -1,19 +1,15 @@
 use libimmixcons::*;
+use libimmixcons_derive::HeapObject;
 use object::*;
 use threading::{immix_register_thread, immix_unregister_thread};
+
+#[derive(HeapObject)]
+// remove `finalize` if you do not have to invoke destructor for object.
+#[gc(finalize)]
 struct Simple {
     x: Gc<i32>,
 }
 
-impl HeapObject for Simple {
-    // remove `finalize` if you do not have to invoke destructor for object.
-    const RTTI: GCRTTI = make_rtti_for!(finalize Simple);
-    fn visit_references(&mut self, tracer: &mut dyn Tracer) {
-        println!("Tracing 'Simple'");
-        self.x.visit_references(tracer);
-    }
-}
-
 impl Drop for Simple {
     fn drop(&mut self) {
         println!("Drop for 'Simple' invoked after GC");
@@ -0,0 +1,158 @@
+//! `#[derive(HeapObject)]` for libimmixcons.
+//!
+//! Derived implementation traces every field with `libimmixcons::object::Trace` and creates `RTTI` with
+//! `make_rtti_for!`. Structs, enums and generic types are supported, type parameters must implement `Trace`.
+//!
+//! Attributes:
+//! - `#[gc(skip)]` on a field: field is not traced. Use it only for fields that never contain GC references.
+//! - `#[gc(finalize)]` on the type: destructor of the type is invoked when object is dead.
+//!
+//! ```ignore
+//! #[derive(HeapObject)]
+//! #[gc(finalize)]
+//! struct Node<T> {
+//!     value: T,
+//!     next: Option<Gc<Node<T>>>,
+//!     #[gc(skip)]
+//!     name: String,
+//! }
+//! ```
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, Meta, NestedMeta,
+};
+
+#[proc_macro_derive(HeapObject, attributes(gc))]
+pub fn derive_heap_object(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+/// Flags set by `#[gc(...)]` attributes.
+#[derive(Default)]
+struct Flags {
+    skip: bool,
+    finalize: bool,
+}
+
+fn parse_flags(attrs: &[Attribute]) -> syn::Result<Flags> {
+    let mut flags = Flags::default();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("gc")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested.iter() {
+                    match nested {
+                        NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
+                            flags.skip = true
+                        }
+                        NestedMeta::Meta(Meta::Path(path)) if path.is_ident("finalize") => {
+                            flags.finalize = true
+                        }
+                        other => {
+                            return Err(Error::new_spanned(other, "expected `skip` or `finalize`"))
+                        }
+                    }
+                }
+            }
+            other => return Err(Error::new_spanned(other, "expected `#[gc(...)]`")),
+        }
+    }
+    Ok(flags)
+}
+
+/// Return pattern that binds traced `fields` and statements that trace them.
+fn trace_fields(fields: &Fields) -> syn::Result<(TokenStream2, TokenStream2)> {
+    let mut bindings = Vec::new();
+    let mut traces = Vec::new();
+    for (ix, field) in fields.iter().enumerate() {
+        let flags = parse_flags(&field.attrs)?;
+        if flags.finalize {
+            return Err(Error::new_spanned(
+                field,
+                "`finalize` is allowed only on the type",
+            ));
+        }
+        if flags.skip {
+            continue;
+        }
+        let binding = format_ident!("__field{}", ix);
+        let member = match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = syn::Index::from(ix);
+                quote!(#index)
+            }
+        };
+        bindings.push(quote!(#member: #binding));
+        traces.push(quote!(::libimmixcons::object::Trace::trace(#binding, tracer);));
+    }
+    Ok((quote!({ #(#bindings,)* .. }), quote!(#(#traces)*)))
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let flags = parse_flags(&input.attrs)?;
+    if flags.skip {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "`skip` is allowed only on fields",
+        ));
+    }
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, traces) = trace_fields(&data.fields)?;
+            quote!(match self { #name #pattern => { #traces } })
+        }
+        Data::Enum(data) if data.variants.is_empty() => quote!(match *self {}),
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            for variant in data.variants.iter() {
+                let variant_name = &variant.ident;
+                let (pattern, traces) = trace_fields(&variant.fields)?;
+                arms.push(quote!(#name::#variant_name #pattern => { #traces }));
+            }
+            quote!(match self { #(#arms)* })
+        }
+        Data::Union(data) => {
+            return Err(Error::new_spanned(
+                data.union_token,
+                "HeapObject can not be derived for unions",
+            ))
+        }
+    };
+
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param
+            .bounds
+            .push(parse_quote!(::libimmixcons::object::Trace));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let rtti = if flags.finalize {
+        quote!(::libimmixcons::make_rtti_for!(finalize Self))
+    } else {
+        quote!(::libimmixcons::make_rtti_for!(Self))
+    };
+    Ok(quote! {
+        impl #impl_generics ::libimmixcons::object::Trace for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn trace(&mut self, tracer: &mut dyn ::libimmixcons::object::Tracer) {
+                #body
+            }
+        }
+
+        impl #impl_generics ::libimmixcons::object::HeapObject for #name #ty_generics #where_clause {
+            const RTTI: ::libimmixcons::object::GCRTTI = #rtti;
+            fn visit_references(&mut self, tracer: &mut dyn ::libimmixcons::object::Tracer) {
+                ::libimmixcons::object::Trace::trace(self, tracer);
+            }
+        }
+    })
+}
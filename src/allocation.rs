@@ -3,7 +3,7 @@ use super::block_allocator::BlockAllocator;
 use super::constants::*;
 use super::space_bitmap::SpaceBitmap;
 use crate::util::*;
-use crate::{object::*, threading::immix_get_tls_state};
+use crate::{object::*, relocation::Relocation, threading::immix_get_tls_state};
 use core::{mem::size_of, ptr::null_mut};
 /// A type alias for the block, the current low and high offset.
 pub type BlockTuple = (*mut ImmixBlock, u16, u16);
@@ -108,6 +108,8 @@ pub struct NormalAllocator {
     unavail_lock: ReentrantMutex,
     /// The current block to allocate from.
     current_block: Option<BlockTuple>,
+    /// Allocate objects without references, blocks of this allocator are marked as `no_scan`.
+    no_scan: bool,
 }
 
 #[cfg(feature = "threaded")]
@@ -115,9 +117,10 @@ use locks::mutex::ReentrantMutex;
 
 impl NormalAllocator {
     /// Create a new `NormalAllocator` backed by the given `BlockAllocator`.
-    pub fn new(block_allocator: *mut BlockAllocator) -> NormalAllocator {
+    pub fn new(block_allocator: *mut BlockAllocator, no_scan: bool) -> NormalAllocator {
         NormalAllocator {
             block_allocator,
+            no_scan,
             unavailable_blocks: Vec::new(),
             recyclable_blocks: Vec::new(),
             current_block: None,
@@ -131,6 +134,11 @@ impl NormalAllocator {
     pub fn set_recyclable_blocks(&mut self, blocks: Vec<*mut ImmixBlock>) {
         self.recyclable_blocks = blocks;
     }
+
+    /// Get the recyclable blocks.
+    pub fn recyclable_blocks(&self) -> &[*mut ImmixBlock] {
+        &self.recyclable_blocks
+    }
 }
 
 impl Allocator for NormalAllocator {
@@ -154,7 +162,11 @@ impl Allocator for NormalAllocator {
         }
         #[cfg(feature = "threaded")]
         {
-            immix_get_tls_state().current_block.take()
+            if self.no_scan {
+                immix_get_tls_state().current_atomic_block.take()
+            } else {
+                immix_get_tls_state().current_block.take()
+            }
         }
     }
 
@@ -165,7 +177,11 @@ impl Allocator for NormalAllocator {
         }
         #[cfg(feature = "threaded")]
         {
-            immix_get_tls_state().current_block = Some(block_tuple);
+            if self.no_scan {
+                immix_get_tls_state().current_atomic_block = Some(block_tuple);
+            } else {
+                immix_get_tls_state().current_block = Some(block_tuple);
+            }
         }
     }
 
@@ -173,6 +189,17 @@ impl Allocator for NormalAllocator {
         unsafe {
             let block = (&mut *self.block_allocator).get_block()?;
             (*block).allocated = true;
+            (*block).no_scan = self.no_scan;
+            if (*self.block_allocator).blacklist.apply(block) {
+                // Block has blacklisted lines, allocate only from holes between them.
+                return match (*block).scan_block((size_of::<ImmixBlock>() - 1) as u16) {
+                    Some((low, high)) => Some((block, low, high)),
+                    None => {
+                        self.handle_full_block(block);
+                        self.get_new_block()
+                    }
+                };
+            }
             Some((block, (LINE_SIZE) as u16, (BLOCK_SIZE - 1) as u16))
         }
     }
@@ -241,15 +268,18 @@ pub struct OverflowAllocator {
 
     /// The current block to allocate from.
     current_block: Option<BlockTuple>,
+    /// Allocate objects without references, blocks of this allocator are marked as `no_scan`.
+    no_scan: bool,
 }
 
 impl OverflowAllocator {
     /// Create a new `OverflowAllocator` backed by the given `BlockAllocator`.
-    pub fn new(block_allocator: *mut BlockAllocator) -> OverflowAllocator {
+    pub fn new(block_allocator: *mut BlockAllocator, no_scan: bool) -> OverflowAllocator {
         OverflowAllocator {
             #[cfg(feature = "threaded")]
             unavail_lock: ReentrantMutex::new(),
             block_allocator,
+            no_scan,
             unavailable_blocks: Vec::new(),
             current_block: None,
         }
@@ -277,7 +307,11 @@ impl Allocator for OverflowAllocator {
         }
         #[cfg(feature = "threaded")]
         {
-            immix_get_tls_state().current_ovf_block.take()
+            if self.no_scan {
+                immix_get_tls_state().current_atomic_ovf_block.take()
+            } else {
+                immix_get_tls_state().current_ovf_block.take()
+            }
         }
     }
 
@@ -288,7 +322,11 @@ impl Allocator for OverflowAllocator {
         }
         #[cfg(feature = "threaded")]
         {
-            immix_get_tls_state().current_ovf_block = Some(block_tuple);
+            if self.no_scan {
+                immix_get_tls_state().current_atomic_ovf_block = Some(block_tuple);
+            } else {
+                immix_get_tls_state().current_ovf_block = Some(block_tuple);
+            }
         }
     }
 
@@ -296,6 +334,17 @@ impl Allocator for OverflowAllocator {
         unsafe {
             let block = (&mut *self.block_allocator).get_block()?;
             (*block).allocated = true;
+            (*block).no_scan = self.no_scan;
+            if (*self.block_allocator).blacklist.apply(block) {
+                // Block has blacklisted lines, allocate only from holes between them.
+                return match (*block).scan_block((size_of::<ImmixBlock>() - 1) as u16) {
+                    Some((low, high)) => Some((block, low, high)),
+                    None => {
+                        self.handle_full_block(block);
+                        self.get_new_block()
+                    }
+                };
+            }
             Some((block, LINE_SIZE as u16, (BLOCK_SIZE - 1) as u16))
         }
     }
@@ -403,13 +452,19 @@ pub struct ImmixSpace {
     pub bitmap: SpaceBitmap<16>,
     /// The nomal allocator for objects smaller than `MEDIUM_OBJECT` bytes.
     allocator: NormalAllocator,
+    /// The allocator for objects without references.
+    atomic_allocator: NormalAllocator,
 
     /// The overflow allocator for objects larger than `MEDIUM_OBJECT` bytes.
     overflow_allocator: OverflowAllocator,
+    /// The overflow allocator for objects without references larger than `MEDIUM_OBJECT` bytes.
+    atomic_overflow_allocator: OverflowAllocator,
     /// The evacuation allocator used during an evacuating collection.
     evac_allocator: EvacAllocator,
     /// The current live mark for new objects. See `Spaces.current_live_mark`.
     current_live_mark: bool,
+    /// Moves of objects with `notify_relocation` RTTI made in current GC cycle.
+    relocations: Vec<Relocation>,
 }
 impl ImmixSpace {
     pub fn filter_fast(&self, addr: Address) -> bool {
@@ -437,6 +492,32 @@ impl ImmixSpace {
 
         None
     }
+    /// Find object that contains `addr`.
+    ///
+    /// This is slow: object bitmap is scanned backwards for at most `LARGE_OBJECT` bytes.
+    pub fn find_interior(&self, addr: Address) -> Option<Address> {
+        unsafe {
+            if !self.filter_fast(addr)
+                || addr.to_usize() < (*self.block_allocator).mmap.aligned() as usize
+            {
+                return None;
+            }
+            let block = ImmixBlock::get_block_ptr(addr) as usize;
+            let low = core::cmp::max(block, addr.to_usize().saturating_sub(LARGE_OBJECT));
+            let mut object = addr.to_usize() & !15;
+            while object >= low {
+                if self.bitmap.test(object) {
+                    let size = (*(object as *mut RawGc)).object_size();
+                    if addr.to_usize() < object + size {
+                        return Some(Address::from(object));
+                    }
+                    return None;
+                }
+                object -= 16;
+            }
+            None
+        }
+    }
     pub fn new(heap_size: usize) -> *mut Self {
         unsafe {
             let block = BlockAllocator::new(heap_size);
@@ -451,15 +532,22 @@ impl ImmixSpace {
                 block_allocator: block,
                 bitmap,
                 evac_allocator: EvacAllocator::new(),
-                allocator: NormalAllocator::new(null_mut()),
-                overflow_allocator: OverflowAllocator::new(null_mut()),
+                allocator: NormalAllocator::new(null_mut(), false),
+                atomic_allocator: NormalAllocator::new(null_mut(), true),
+                overflow_allocator: OverflowAllocator::new(null_mut(), false),
+                atomic_overflow_allocator: OverflowAllocator::new(null_mut(), true),
                 current_live_mark: false,
+                relocations: Vec::new(),
             };
 
             this.allocator.block_allocator =
                 this.block_allocator as *const BlockAllocator as *mut _;
+            this.atomic_allocator.block_allocator =
+                this.block_allocator as *const BlockAllocator as *mut _;
             this.overflow_allocator.block_allocator =
                 this.block_allocator as *const BlockAllocator as *mut _;
+            this.atomic_overflow_allocator.block_allocator =
+                this.block_allocator as *const BlockAllocator as *mut _;
             let ptr = libc::malloc(size_of::<Self>()).cast::<Self>();
             ptr.write(this);
             ptr
@@ -477,14 +565,67 @@ impl ImmixSpace {
         }
     }
 
+    /// Return true if `object` is allocated in a block for objects without references.
+    ///
+    /// # Safety
+    /// `object` must point into immix space.
+    pub unsafe fn is_no_scan(&self, object: Address) -> bool {
+        (*ImmixBlock::get_block_ptr(object)).no_scan
+    }
+
     /// Set the current live mark to `current_live_mark`.
     pub fn set_current_live_mark(&mut self, current_live_mark: bool) {
         self.current_live_mark = current_live_mark;
     }
 
-    /// Set the recyclable blocks for the `NormalAllocator`.
+    /// Update blacklist after marking with addresses of conservative roots that did not point to any object.
+    ///
+    /// Addresses that point into free lines or unallocated blocks are blacklisted and blacklisted lines of
+    /// recyclable blocks are marked so the `NormalAllocator` skips them.
+    pub fn update_blacklist(&mut self, false_roots: &[Address]) {
+        unsafe {
+            let block_allocator = &mut *self.block_allocator;
+            block_allocator.blacklist.age();
+            let begin = block_allocator.mmap.aligned() as usize;
+            for addr in false_roots.iter() {
+                if addr.to_usize() < begin {
+                    continue;
+                }
+                let block = ImmixBlock::get_block_ptr(*addr);
+                let line = ImmixBlock::object_to_line_num(*addr);
+                // first line is occupied by block header.
+                if line != 0 && (!(*block).allocated || !(*block).line_is_marked(line)) {
+                    block_allocator.blacklist.add(addr.to_usize());
+                }
+            }
+            for &block in self
+                .allocator
+                .recyclable_blocks()
+                .iter()
+                .chain(self.atomic_allocator.recyclable_blocks())
+            {
+                block_allocator.blacklist.apply(block);
+            }
+        }
+    }
+
+    /// Return number of bytes in blacklisted lines.
+    pub fn blacklisted_bytes(&self) -> usize {
+        unsafe { (*self.block_allocator).blacklist.blacklisted_bytes() }
+    }
+
+    /// Set the recyclable blocks for the `NormalAllocator` and the allocator for objects without references.
     pub fn set_recyclable_blocks(&mut self, blocks: Vec<*mut ImmixBlock>) {
+        let (no_scan, blocks) = blocks
+            .into_iter()
+            .partition(|&block| unsafe { (*block).no_scan });
         self.allocator.set_recyclable_blocks(blocks);
+        self.atomic_allocator.set_recyclable_blocks(no_scan);
+    }
+
+    /// Take moves of objects with `notify_relocation` RTTI recorded since the last call.
+    pub fn take_relocations(&mut self) -> Vec<Relocation> {
+        core::mem::take(&mut self.relocations)
     }
 
     /// Extend the list of free blocks in the `EvacAllocator` for evacuation.
@@ -495,26 +636,36 @@ impl ImmixSpace {
     /// collections.
     pub fn get_all_blocks(&mut self) -> Vec<*mut ImmixBlock> {
         let mut normal_blocks = self.allocator.get_all_blocks();
+        let mut atomic_blocks = self.atomic_allocator.get_all_blocks();
         let mut overflow_blocks = self.overflow_allocator.get_all_blocks();
+        let mut atomic_overflow_blocks = self.atomic_overflow_allocator.get_all_blocks();
         let mut evac_blocks = self.evac_allocator.get_all_blocks();
         return normal_blocks
             .drain(..)
+            .chain(atomic_blocks.drain(..))
             .chain(overflow_blocks.drain(..))
+            .chain(atomic_overflow_blocks.drain(..))
             .chain(evac_blocks.drain(..))
             .collect();
     }
+    /// Allocate object of `size` bytes. Objects are aligned to 16 bytes, if `align` is larger object is allocated
+    /// with enough space before it to align it. The space before object does not belong to any object: the bitmap
+    /// points to the object itself and lines of the space are freed by the next cycle.
     #[inline]
-    pub fn allocate(&mut self, size: usize, _vtable: usize) -> *mut RawGc {
-        let ptr = if size < MEDIUM_OBJECT {
-            self.allocator.allocate(size)
-        } else {
-            self.overflow_allocator.allocate(size)
+    pub fn allocate(&mut self, size: usize, align: usize, no_scan: bool) -> *mut RawGc {
+        let padding = if align > 16 { align - 16 } else { 0 };
+        let ptr = match (no_scan, size + padding < MEDIUM_OBJECT) {
+            (false, true) => self.allocator.allocate(size + padding),
+            (false, false) => self.overflow_allocator.allocate(size + padding),
+            (true, true) => self.atomic_allocator.allocate(size + padding),
+            (true, false) => self.atomic_overflow_allocator.allocate(size + padding),
         };
         {
             if ptr.is_non_null() {
-                let ptr = ptr.to_mut_ptr::<RawGc>();
+                let ptr = align_usize(ptr.to_usize(), align) as *mut RawGc;
 
                 self.set_gc_object(Address::from_ptr(ptr));
+                return ptr;
             }
         }
 
@@ -536,13 +687,37 @@ impl ImmixSpace {
         let block_info = ImmixBlock::get_block_ptr(Address::from_ptr(addr));
         let is_pinned = (*addr).is_pinned();
         let is_candidate = (*block_info).evacuation_candidate;
-        if is_pinned || !is_candidate {
+        // Evacuation allocator does not segregate objects without references.
+        if is_pinned || !is_candidate || (*block_info).no_scan {
             return None;
         }
         let size = (&*addr).object_size();
-        let new_object = self.evac_allocator.allocate(align_usize(size, 16));
+        // Object that was hashed by address and is moved for the first time gets a slot for the hash.
+        let add_hash_slot = (*addr).is_hashed() && !(*addr).is_hashed_and_moved();
+        let new_size = if add_hash_slot {
+            align_usize((*addr).hash_slot_offset() + 8, 16)
+        } else {
+            size
+        };
+        // Over-aligned objects are moved to the address with the same alignment so the padding between header
+        // and value does not change.
+        let align = (*addr).rtti().alignment;
+        let padding = if align > 16 { align - 16 } else { 0 };
+        let new_object = self
+            .evac_allocator
+            .allocate(align_usize(new_size, 16) + padding);
         if new_object.is_non_null() {
+            let new_object = Address::from(align_usize(new_object.to_usize(), align));
             core::ptr::copy_nonoverlapping(addr as *const u8, new_object.to_mut_ptr::<u8>(), size);
+            if add_hash_slot {
+                (*new_object.to_mut_ptr::<RawGc>()).set_moved_hash(addr as usize);
+            }
+            if (*addr).rtti().notify_relocation {
+                self.relocations.push(Relocation {
+                    old: addr.cast(),
+                    new: new_object.to_mut_ptr(),
+                });
+            }
 
             self.set_gc_object(new_object);
             return Some(new_object);
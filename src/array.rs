@@ -0,0 +1,276 @@
+//! Variable-length objects: [GcArray], [GcBytes] and [GcStr].
+//!
+//! Length and elements are stored inline in one heap object right after the object header, so
+//! [HeapObject::heap_size] of these types depends on their length. Like any other object, arrays of `LARGE_OBJECT`
+//! bytes or more are allocated in the medium object space and arrays above `MEDIUM_OBJECT_LIMIT` bytes in the large
+//! object space.
+//!
+//! Values of these types can not be created on the stack, they exist only behind [Gc].
+use crate::{immix_alloc, immix_alloc_atomic, make_rtti_for, object::*};
+use core::fmt;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+/// Allocate object of type `T` with `len` elements of type `E` after it. Only length is initialized.
+///
+/// # Panics
+/// Panics if size of the object overflows `usize`.
+unsafe fn alloc_with_len<T: HeapObject, E>(len: usize) -> Gc<T> {
+    assert!(
+        align_of::<E>() <= size_of::<usize>(),
+        "elements with alignment above word size are not supported"
+    );
+    let size = len
+        .checked_mul(size_of::<E>())
+        .and_then(|size| size.checked_add(size_of::<RawGc>() + size_of::<T>()))
+        .expect("variable-length object size overflows usize");
+    let rtti = object_ty_of_type::<T>() as *const GCRTTI;
+    let ptr = if T::NO_SCAN {
+        immix_alloc_atomic(size, rtti)
+    } else {
+        immix_alloc(size, rtti)
+    } as *mut RawGc;
+    assert!(!ptr.is_null(), "out of memory");
+    (*ptr).data().cast::<usize>().write(len);
+    Gc {
+        marker: Default::default(),
+        ptr: NonNull::new_unchecked(ptr),
+    }
+}
+
+/// Fixed-length array of `T` stored inline in GC heap. Elements are traced with [Trace].
+#[repr(C)]
+pub struct GcArray<T> {
+    length: usize,
+    data: [T; 0],
+}
+
+impl<T: Trace> GcArray<T> {
+    /// Allocate array of `len` clones of `value`.
+    ///
+    /// `Clone` of `T` must not allocate in GC heap: elements are not visible to GC until array is filled.
+    pub fn new(len: usize, value: T) -> Gc<Self>
+    where
+        T: Clone,
+    {
+        unsafe { Self::alloc_filled(len, core::iter::repeat_with(|| value.clone())) }
+    }
+
+    /// Allocate array with clones of elements of `values`. See [GcArray::new] for restrictions on `Clone`.
+    pub fn from_slice(values: &[T]) -> Gc<Self>
+    where
+        T: Clone,
+    {
+        unsafe { Self::alloc_filled(values.len(), values.iter().cloned()) }
+    }
+
+    /// Allocate array of `len` elements and fill it with first `len` items of `values`.
+    ///
+    /// Length is raised only after each element is written, so if `values` panics the finalizer drops only
+    /// initialized elements.
+    unsafe fn alloc_filled(len: usize, values: impl Iterator<Item = T>) -> Gc<Self> {
+        let array = alloc_with_len::<Self, T>(len);
+        let data = (*array.ptr.as_ptr()).data().cast::<Self>();
+        (*data).length = 0;
+        for value in values.take(len) {
+            (*data).data.as_mut_ptr().add((*data).length).write(value);
+            (*data).length += 1;
+        }
+        array
+    }
+}
+
+impl<T> GcArray<T> {
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.data.as_ptr(), self.length) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.data.as_mut_ptr(), self.length) }
+    }
+}
+
+impl<T: Trace> HeapObject for GcArray<T> {
+    const RTTI: GCRTTI = GCRTTI {
+        needs_finalization: core::mem::needs_drop::<T>(),
+        ..make_rtti_for!(Self)
+    };
+
+    fn visit_references(&mut self, tracer: &mut dyn Tracer) {
+        self.as_mut_slice().trace(tracer);
+    }
+
+    fn heap_size(&self) -> usize {
+        size_of::<Self>() + self.length * size_of::<T>()
+    }
+}
+
+impl<T> Drop for GcArray<T> {
+    fn drop(&mut self) {
+        unsafe { core::ptr::drop_in_place(self.as_mut_slice()) }
+    }
+}
+
+impl<T> Deref for GcArray<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T> DerefMut for GcArray<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for GcArray<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+/// Byte array stored inline in GC heap. Never scanned by GC.
+#[repr(C)]
+pub struct GcBytes {
+    length: usize,
+    data: [u8; 0],
+}
+
+impl GcBytes {
+    /// Allocate zeroed byte array of `len` bytes.
+    pub fn zeroed(len: usize) -> Gc<Self> {
+        unsafe {
+            let bytes = alloc_with_len::<Self, u8>(len);
+            core::ptr::write_bytes((*bytes.ptr.as_ptr()).data().add(size_of::<Self>()), 0, len);
+            bytes
+        }
+    }
+
+    /// Allocate copy of `bytes`.
+    pub fn from_slice(bytes: &[u8]) -> Gc<Self> {
+        unsafe {
+            let result = alloc_with_len::<Self, u8>(bytes.len());
+            core::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                (*result.ptr.as_ptr()).data().add(size_of::<Self>()),
+                bytes.len(),
+            );
+            result
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.data.as_ptr(), self.length) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.data.as_mut_ptr(), self.length) }
+    }
+}
+
+impl HeapObject for GcBytes {
+    const RTTI: GCRTTI = make_rtti_for!(GcBytes);
+    const NO_SCAN: bool = true;
+
+    fn heap_size(&self) -> usize {
+        size_of::<Self>() + self.length
+    }
+}
+
+impl Deref for GcBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl DerefMut for GcBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.as_mut_slice()
+    }
+}
+
+impl fmt::Debug for GcBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+/// Immutable UTF-8 string stored inline in GC heap. Never scanned by GC.
+#[repr(C)]
+pub struct GcStr {
+    bytes: GcBytes,
+}
+
+impl GcStr {
+    /// Allocate copy of `s`.
+    pub fn new(s: &str) -> Gc<Self> {
+        unsafe {
+            let result = alloc_with_len::<Self, u8>(s.len());
+            core::ptr::copy_nonoverlapping(
+                s.as_ptr(),
+                (*result.ptr.as_ptr()).data().add(size_of::<Self>()),
+                s.len(),
+            );
+            result
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.length == 0
+    }
+
+    pub fn as_str(&self) -> &str {
+        unsafe { core::str::from_utf8_unchecked(self.bytes.as_slice()) }
+    }
+}
+
+impl HeapObject for GcStr {
+    const RTTI: GCRTTI = make_rtti_for!(GcStr);
+    const NO_SCAN: bool = true;
+
+    fn heap_size(&self) -> usize {
+        self.bytes.heap_size()
+    }
+}
+
+impl Deref for GcStr {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for GcStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl fmt::Display for GcStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
@@ -0,0 +1,471 @@
+//! BDWGC compatible API.
+//!
+//! Exports subset of `GC_*` functions from `gc.h` so C code written against BDWGC can be relinked
+//! against libimmixcons without changes. All objects allocated through this API are scanned
+//! conservatively, conservative roots and heap references might point anywhere inside of an object
+//! (like BDWGC with `GC_all_interior_pointers` set) and static data segments are scanned for roots.
+//!
+//! Object layout:
+//! ```text
+//! | GCObject header | (padding in large object space) | size | user data ... |
+//!                                                             ^ pointer returned to user, aligned to 16
+//! ```
+//!
+//! NOTE: Finalizers are invoked during GC cycle right after marking so they must not allocate or
+//! trigger collection.
+#[cfg(feature = "threaded")]
+use crate::util::locks::mutex::Mutex;
+use crate::{
+    collector::{self, INTERIOR_POINTERS},
+    constants::*,
+    immix_alloc, immix_alloc_atomic, immix_collect, immix_init, immix_noop_callback,
+    immix_noop_visit,
+    large_object_space::PreciseAllocation,
+    object::*,
+    static_roots, threading, SPACE,
+};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+use libc::c_void;
+#[cfg(feature = "threaded")]
+use parking_lot::lock_api::RawMutex;
+
+/// Heap size used when `GC_MAXIMUM_HEAP_SIZE` environment variable is not set.
+const DEFAULT_HEAP_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Set by the first `GC_init` call, GC itself might be already initialized by `immix_init` before it.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+pub type GcFinalizationProc = Option<extern "C" fn(obj: *mut c_void, client_data: *mut c_void)>;
+
+struct Compat {
+    /// User pointers to uncollectable objects, this vector is scanned as conservative roots.
+    uncollectable: Vec<usize>,
+    /// User pointer -> finalizer and its client data.
+    finalizers: BTreeMap<usize, (GcFinalizationProc, usize)>,
+    #[cfg(feature = "threaded")]
+    lock: Mutex,
+}
+
+static mut COMPAT: Compat = Compat {
+    uncollectable: Vec::new(),
+    finalizers: BTreeMap::new(),
+    #[cfg(feature = "threaded")]
+    lock: Mutex::new(),
+};
+
+fn with_compat<R>(f: impl FnOnce(&mut Compat) -> R) -> R {
+    unsafe {
+        let compat = &mut *core::ptr::addr_of_mut!(COMPAT);
+        #[cfg(feature = "threaded")]
+        {
+            compat.lock.lock();
+        }
+        let result = f(compat);
+        #[cfg(feature = "threaded")]
+        {
+            compat.lock.unlock();
+        }
+        result
+    }
+}
+
+/// Offset of user data from object header. User data is aligned to 16 and preceded by its size.
+fn data_offset(object: *mut RawGc) -> usize {
+    if PreciseAllocation::is_precise(object.cast()) {
+        PreciseAllocation::ALIGNED_VALUE_OFFSET
+    } else {
+        align_usize(core::mem::size_of::<RawGc>() + 8, 16)
+    }
+}
+
+extern "C" fn heap_size(object: *mut u8) -> usize {
+    unsafe {
+        let offset = data_offset(object.cast());
+        offset + object.add(offset - 8).cast::<usize>().read()
+    }
+}
+
+extern "C" fn finalize(object: *mut u8) {
+    let user = object as usize + data_offset(object.cast());
+    let finalizer = with_compat(|compat| compat.finalizers.remove(&user));
+    if let Some((Some(finalizer), client_data)) = finalizer {
+        finalizer(user as *mut c_void, client_data as *mut c_void);
+    }
+}
+
+static BDWGC_RTTI: GCRTTI = GCRTTI {
+    heap_size,
+    visit_references: immix_noop_visit,
+    needs_finalization: false,
+    finalizer: None,
+    conservative: true,
+    alignment: 0,
+    notify_relocation: false,
+};
+
+static BDWGC_ATOMIC_RTTI: GCRTTI = GCRTTI {
+    heap_size,
+    visit_references: immix_noop_visit,
+    needs_finalization: false,
+    finalizer: None,
+    conservative: false,
+    alignment: 0,
+    notify_relocation: false,
+};
+
+static BDWGC_FINALIZABLE_RTTI: GCRTTI = GCRTTI {
+    heap_size,
+    visit_references: immix_noop_visit,
+    needs_finalization: true,
+    finalizer: Some(finalize),
+    conservative: true,
+    alignment: 0,
+    notify_relocation: false,
+};
+
+/// RTTI of pointer-free objects with registered finalizer, they stay unscanned.
+static BDWGC_ATOMIC_FINALIZABLE_RTTI: GCRTTI = GCRTTI {
+    heap_size,
+    visit_references: immix_noop_visit,
+    needs_finalization: true,
+    finalizer: Some(finalize),
+    conservative: false,
+    alignment: 0,
+    notify_relocation: false,
+};
+
+/// Return true if `object` was allocated with `GC_malloc_atomic`.
+unsafe fn is_atomic(object: *mut RawGc) -> bool {
+    let vtable = (*object).vtable();
+    vtable == &BDWGC_ATOMIC_RTTI as *const GCRTTI as usize
+        || vtable == &BDWGC_ATOMIC_FINALIZABLE_RTTI as *const GCRTTI as usize
+}
+
+extern "C" fn scan_uncollectable(_: *mut u8, _: TracerPtr, cons_tracer: ConservativeTracer) {
+    unsafe {
+        let compat = &*core::ptr::addr_of!(COMPAT);
+        let begin = compat.uncollectable.as_ptr() as *mut *mut u8;
+        cons_tracer.add(begin, begin.add(compat.uncollectable.len()));
+    }
+}
+
+unsafe fn allocate(size: usize, atomic: bool) -> *mut c_void {
+    if !INITIALIZED.load(Ordering::Acquire) {
+        GC_init();
+    }
+    let mut total = align_usize(core::mem::size_of::<RawGc>() + 8, 16) + size;
+    if align_usize(total, 16) >= LARGE_OBJECT {
+        // object is placed either in medium or in large object space, `data_offset` depends on that
+        total = core::cmp::max(total, PreciseAllocation::ALIGNED_VALUE_OFFSET + size);
+    }
+    let object = if atomic {
+        immix_alloc_atomic(total, &BDWGC_ATOMIC_RTTI)
+    } else {
+        immix_alloc(total, &BDWGC_RTTI)
+    }
+    .cast::<RawGc>();
+    if object.is_null() {
+        return null_mut();
+    }
+    let user = object.cast::<u8>().add(data_offset(object));
+    user.sub(8).cast::<usize>().write(size);
+    if !atomic {
+        core::ptr::write_bytes(user, 0, size);
+    }
+    user.cast()
+}
+
+/// Return object that contains `p`.
+pub(crate) unsafe fn object_of(p: *mut c_void) -> Option<*mut RawGc> {
+    if p.is_null() || SPACE.is_null() {
+        return None;
+    }
+    collector::find_object(&*(*SPACE).immix, &(*SPACE).mos, &(*SPACE).los, p.cast())
+}
+
+/// Initialize GC and register current thread.
+///
+/// Heap size is taken from `GC_MAXIMUM_HEAP_SIZE` environment variable, 1GB by default. If GC was already
+/// initialized with `immix_init` only interior pointers, static roots and uncollectable objects are enabled.
+#[no_mangle]
+pub extern "C" fn GC_init() {
+    unsafe {
+        if INITIALIZED.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        INTERIOR_POINTERS.store(true, Ordering::Release);
+        static_roots::immix_enable_static_roots();
+        let existing = !SPACE.is_null();
+        if !existing {
+            let mut heap_size = DEFAULT_HEAP_SIZE;
+            let var = libc::getenv(b"GC_MAXIMUM_HEAP_SIZE\0".as_ptr().cast());
+            if !var.is_null() {
+                let size = libc::strtoull(var, null_mut(), 10) as usize;
+                if size != 0 {
+                    heap_size = size;
+                }
+            }
+            immix_init(heap_size, 0, immix_noop_callback, null_mut());
+        }
+        (*SPACE)
+            .collect_roots_callback
+            .push((scan_uncollectable, null_mut()));
+        if !existing {
+            threading::immix_register_thread();
+        }
+    }
+}
+
+/// Allocate `size` bytes of zeroed memory that is scanned conservatively.
+#[no_mangle]
+pub extern "C" fn GC_malloc(size: usize) -> *mut c_void {
+    unsafe { allocate(size, false) }
+}
+
+/// Allocate `size` bytes that are never scanned. Memory is not zeroed.
+#[no_mangle]
+pub extern "C" fn GC_malloc_atomic(size: usize) -> *mut c_void {
+    unsafe { allocate(size, true) }
+}
+
+/// Allocate `size` bytes that are scanned but never collected unless freed with `GC_free`.
+#[no_mangle]
+pub extern "C" fn GC_malloc_uncollectable(size: usize) -> *mut c_void {
+    let p = GC_malloc(size);
+    if !p.is_null() {
+        with_compat(|compat| compat.uncollectable.push(p as usize));
+    }
+    p
+}
+
+#[no_mangle]
+pub extern "C" fn GC_malloc_ignore_off_page(size: usize) -> *mut c_void {
+    GC_malloc(size)
+}
+
+#[no_mangle]
+pub extern "C" fn GC_malloc_atomic_ignore_off_page(size: usize) -> *mut c_void {
+    GC_malloc_atomic(size)
+}
+
+/// Explicitly deallocate object. Only uncollectable objects are affected, other objects are reclaimed by GC.
+#[no_mangle]
+pub extern "C" fn GC_free(p: *mut c_void) {
+    if p.is_null() {
+        return;
+    }
+    with_compat(|compat| {
+        if let Some(ix) = compat.uncollectable.iter().position(|&x| x == p as usize) {
+            compat.uncollectable.swap_remove(ix);
+        }
+    });
+}
+
+/// Return size of object that `p` points to.
+#[no_mangle]
+pub extern "C" fn GC_size(p: *const c_void) -> usize {
+    unsafe {
+        let base = GC_base(p as *mut c_void);
+        if base.is_null() {
+            return 0;
+        }
+        base.cast::<usize>().sub(1).read()
+    }
+}
+
+/// Return pointer to the beginning of object that contains `p` or null if `p` does not point into GC heap.
+#[no_mangle]
+pub extern "C" fn GC_base(p: *mut c_void) -> *mut c_void {
+    unsafe {
+        match object_of(p) {
+            Some(object) => object.cast::<u8>().add(data_offset(object)).cast(),
+            None => null_mut(),
+        }
+    }
+}
+
+/// Reallocate object `p` to `size` bytes. Object kind (atomic, uncollectable) is preserved.
+#[no_mangle]
+pub extern "C" fn GC_realloc(p: *mut c_void, size: usize) -> *mut c_void {
+    unsafe {
+        if p.is_null() {
+            return GC_malloc(size);
+        }
+        if size == 0 {
+            GC_free(p);
+            return null_mut();
+        }
+        let object = match object_of(p) {
+            Some(object) => object,
+            None => return null_mut(),
+        };
+        let atomic = is_atomic(object);
+        let uncollectable =
+            with_compat(|compat| compat.uncollectable.iter().any(|&x| x == p as usize));
+        let new = if uncollectable {
+            GC_malloc_uncollectable(size)
+        } else {
+            allocate(size, atomic)
+        };
+        if new.is_null() {
+            return null_mut();
+        }
+        let old_size = GC_size(p);
+        core::ptr::copy_nonoverlapping(p.cast::<u8>(), new.cast::<u8>(), old_size.min(size));
+        if uncollectable {
+            GC_free(p);
+        }
+        new
+    }
+}
+
+/// Register `finalizer` that is invoked with `obj` and `client_data` when `obj` becomes unreachable.
+/// Null `finalizer` removes previously registered finalizer. Previous finalizer and its client data are
+/// stored in `old_finalizer` and `old_client_data` if they are not null.
+#[no_mangle]
+pub extern "C" fn GC_register_finalizer(
+    obj: *mut c_void,
+    finalizer: GcFinalizationProc,
+    client_data: *mut c_void,
+    old_finalizer: *mut GcFinalizationProc,
+    old_client_data: *mut *mut c_void,
+) {
+    unsafe {
+        let object = match object_of(obj) {
+            Some(object) => object,
+            None => return,
+        };
+        let user = object as usize + data_offset(object);
+        let old = with_compat(|compat| {
+            if finalizer.is_some() {
+                compat
+                    .finalizers
+                    .insert(user, (finalizer, client_data as usize))
+            } else {
+                compat.finalizers.remove(&user)
+            }
+        });
+        let (old_fn, old_data) = old.unwrap_or((None, 0));
+        if !old_finalizer.is_null() {
+            old_finalizer.write(old_fn);
+        }
+        if !old_client_data.is_null() {
+            old_client_data.write(old_data as *mut c_void);
+        }
+        let finalizable = if is_atomic(object) {
+            &BDWGC_ATOMIC_FINALIZABLE_RTTI
+        } else {
+            &BDWGC_FINALIZABLE_RTTI
+        } as *const GCRTTI as usize;
+        if finalizer.is_some() && (*object).vtable() != finalizable {
+            (*object).set_vtable(finalizable);
+            (*SPACE).add_finalizable(object);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn GC_register_finalizer_ignore_self(
+    obj: *mut c_void,
+    finalizer: GcFinalizationProc,
+    client_data: *mut c_void,
+    old_finalizer: *mut GcFinalizationProc,
+    old_client_data: *mut *mut c_void,
+) {
+    GC_register_finalizer(obj, finalizer, client_data, old_finalizer, old_client_data)
+}
+
+#[no_mangle]
+pub extern "C" fn GC_register_finalizer_no_order(
+    obj: *mut c_void,
+    finalizer: GcFinalizationProc,
+    client_data: *mut c_void,
+    old_finalizer: *mut GcFinalizationProc,
+    old_client_data: *mut *mut c_void,
+) {
+    GC_register_finalizer(obj, finalizer, client_data, old_finalizer, old_client_data)
+}
+
+/// Perform full non-moving collection.
+#[no_mangle]
+pub extern "C" fn GC_gcollect() {
+    if !INITIALIZED.load(Ordering::Acquire) {
+        GC_init();
+    }
+    immix_collect(false);
+}
+
+/// Return number of bytes in Immix blocks in use, committed medium object chunks and large objects.
+#[no_mangle]
+pub extern "C" fn GC_get_heap_size() -> usize {
+    unsafe {
+        if SPACE.is_null() {
+            return 0;
+        }
+        let block_allocator = &*(*(*SPACE).immix).block_allocator;
+        let blocks =
+            (block_allocator.total_blocks() - block_allocator.available_blocks()) * BLOCK_SIZE;
+        blocks + (*SPACE).mos.committed_bytes() + (*SPACE).los.live_bytes()
+    }
+}
+
+/// Return number of bytes in free Immix blocks.
+#[no_mangle]
+pub extern "C" fn GC_get_free_bytes() -> usize {
+    unsafe {
+        if SPACE.is_null() {
+            return 0;
+        }
+        (*(*(*SPACE).immix).block_allocator).available_blocks() * BLOCK_SIZE
+    }
+}
+
+/// Add memory region from `begin` to `end` that is scanned for roots.
+#[no_mangle]
+pub extern "C" fn GC_add_roots(begin: *mut c_void, end: *mut c_void) {
+    GC_init();
+    static_roots::immix_add_roots(begin as usize, end as usize);
+}
+
+#[no_mangle]
+pub extern "C" fn GC_remove_roots(begin: *mut c_void, end: *mut c_void) {
+    static_roots::immix_remove_roots(begin as usize, end as usize);
+}
+
+#[no_mangle]
+pub extern "C" fn GC_register_my_thread(_stack_base: *const c_void) -> i32 {
+    threading::immix_register_thread();
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn GC_unregister_my_thread() -> i32 {
+    // without `threaded` feature unregistering thread destroys the heap.
+    #[cfg(feature = "threaded")]
+    {
+        threading::immix_unregister_thread();
+    }
+    0
+}
+
+/// Interior pointers are always recognized, this function exists only for compatibility.
+#[no_mangle]
+pub extern "C" fn GC_set_all_interior_pointers(_value: i32) {}
+
+#[no_mangle]
+pub extern "C" fn GC_strdup(s: *const libc::c_char) -> *mut libc::c_char {
+    if s.is_null() {
+        return null_mut();
+    }
+    unsafe {
+        let len = libc::strlen(s);
+        let copy = GC_malloc_atomic(len + 1).cast::<libc::c_char>();
+        if !copy.is_null() {
+            core::ptr::copy_nonoverlapping(s, copy, len + 1);
+        }
+        copy
+    }
+}
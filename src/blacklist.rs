@@ -0,0 +1,74 @@
+//! Blacklisting of false conservative pointers.
+//!
+//! Conservative scanning treats any word that points to an allocated object as a root. A word that points
+//! into a free line or into an unallocated block does not keep anything alive yet, but as soon as an object is
+//! allocated there it becomes a false root that retains garbage and pins the block. Just like BDWGC does for
+//! pages, lines hit by such words are blacklisted for `BLACKLIST_CYCLES` GC cycles and allocators skip them.
+use crate::block::ImmixBlock;
+use crate::constants::*;
+use alloc::collections::BTreeMap;
+
+pub struct Blacklist {
+    /// Blacklisted line address -> number of GC cycles left.
+    lines: BTreeMap<usize, u8>,
+}
+
+impl Default for Blacklist {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Blacklist {
+    pub fn new() -> Self {
+        Self {
+            lines: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Blacklist line that contains `addr`.
+    pub fn add(&mut self, addr: usize) {
+        self.lines.insert(addr & !(LINE_SIZE - 1), BLACKLIST_CYCLES);
+    }
+
+    /// Called once per GC cycle, removes lines that were blacklisted for `BLACKLIST_CYCLES` cycles.
+    pub fn age(&mut self) {
+        self.lines.retain(|_, cycles| {
+            *cycles -= 1;
+            *cycles != 0
+        });
+    }
+
+    /// Return true if line that contains `addr` is blacklisted.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.lines.contains_key(&(addr & !(LINE_SIZE - 1)))
+    }
+
+    /// Return number of blacklisted bytes.
+    pub fn blacklisted_bytes(&self) -> usize {
+        self.lines.len() * LINE_SIZE
+    }
+
+    /// Mark blacklisted lines of `block` in its line map so allocators do not allocate in them.
+    ///
+    /// Returns true if `block` has at least one blacklisted line.
+    ///
+    /// # Safety
+    /// `block` must point to a block inside of Immix space.
+    pub unsafe fn apply(&self, block: *mut ImmixBlock) -> bool {
+        if self.lines.is_empty() {
+            return false;
+        }
+        let begin = block as usize;
+        let mut any = false;
+        for (&line, _) in self.lines.range(begin..begin + BLOCK_SIZE) {
+            (*block).line_map.set(line, begin);
+            any = true;
+        }
+        any
+    }
+}
@@ -14,6 +14,8 @@ pub struct ImmixBlock {
     /// How many holes in this block
     pub hole_count: u32,
     pub evacuation_candidate: bool,
+    /// Block holds only objects without references, such objects are never scanned or evacuated.
+    pub no_scan: bool,
     //pub map: memmap::MmapMut,
 }
 
@@ -47,6 +49,7 @@ impl ImmixBlock {
                 allocated: false,
                 hole_count: 0,
                 evacuation_candidate: false,
+                no_scan: false,
             });
 
             &mut *ptr
@@ -176,8 +179,9 @@ impl ImmixBlock {
             let obj = &mut *object_ptr;
 
             let size = obj.object_size();
+            let last_line = Self::object_to_line_num(object.offset(size - 1));
 
-            for line in line_num..(line_num + (size / LINE_SIZE) + 1) {
+            for line in line_num..=last_line {
                 if mark {
                     self.line_map.set(b + (line * LINE_SIZE), b);
                     //debug_assert!(self.line_map.test(b + (line * LINE_SIZE), b));
@@ -234,6 +238,7 @@ impl ImmixBlock {
         self.allocated = false;
         self.hole_count = 0;
         self.evacuation_candidate = false;
+        self.no_scan = false;
     }
     pub fn line_object_mark(&mut self, object: Address) {
         self.modify_line(object, true);
@@ -1,3 +1,4 @@
+use super::blacklist::Blacklist;
 use super::block::ImmixBlock;
 use super::constants::*;
 #[cfg(feature = "threaded")]
@@ -32,6 +33,22 @@ mod _win {
                 }
             }
         }
+        /// Reserve `size` bytes of address space, memory is committed with `commit`. Returns `None` if memory could
+        /// not be reserved.
+        pub fn reserve(size: usize) -> Option<Self> {
+            unsafe {
+                let mem = VirtualAlloc(null_mut(), size, MEM_RESERVE, PAGE_READWRITE);
+                if mem.is_null() {
+                    return None;
+                }
+                let mem = mem as *mut u8;
+                Some(Self {
+                    start: mem,
+                    end: mem.add(size),
+                    size,
+                })
+            }
+        }
         /// Return a `BLOCK_SIZE` aligned pointer to the mmap'ed region.
         pub fn aligned(&self) -> *mut u8 {
             let offset = BLOCK_SIZE - (self.start as usize) % BLOCK_SIZE;
@@ -99,6 +116,28 @@ mod _unix {
                 }
             }
         }
+        /// Reserve `size` bytes of address space, swap space is not reserved for it so pages take memory only once
+        /// they are touched. Returns `None` if memory could not be mapped.
+        pub fn reserve(size: usize) -> Option<Self> {
+            unsafe {
+                let map = libc::mmap(
+                    core::ptr::null_mut(),
+                    size as _,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_NORESERVE,
+                    -1,
+                    0,
+                );
+                if map == libc::MAP_FAILED {
+                    return None;
+                }
+                Some(Self {
+                    start: map as *mut u8,
+                    end: (map as usize + size) as *mut u8,
+                    size,
+                })
+            }
+        }
         /// Return a `BLOCK_SIZE` aligned pointer to the mmap'ed region.
         pub fn aligned(&self) -> *mut u8 {
             let offset = BLOCK_SIZE - (self.start as usize) % BLOCK_SIZE;
@@ -153,6 +192,8 @@ pub struct BlockAllocator {
     pub data_bound: *mut u8,
     pub data: *mut u8,
     pub mmap: Mmap,
+    /// Lines that allocators should skip, see `Blacklist`.
+    pub blacklist: Blacklist,
 }
 
 impl BlockAllocator {
@@ -173,7 +214,7 @@ impl BlockAllocator {
             data: map.aligned(),
             data_bound: map.end(),
             free_blocks: alloc::vec::Vec::new(),
-
+            blacklist: Blacklist::new(),
             mmap: map,
         };
         debug_assert!(this.data as usize % BLOCK_SIZE == 0);
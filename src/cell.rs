@@ -0,0 +1,188 @@
+//! Interior mutability for GC objects.
+//!
+//! [GcCell] and [GcRefCell] are `Cell` and `RefCell` for values that are stored in GC heap. Every write to them goes
+//! through [write_barrier], so collectors that have to track heap writes (generational or concurrent) need to hook
+//! only that function. Build with `strict-mutability` feature to remove `DerefMut` of [Gc] and make these cells the
+//! only way to mutate objects.
+use crate::object::*;
+use core::cell::{Cell, UnsafeCell};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+/// Write barrier. Invoked before value stored at `slot` is replaced or mutably borrowed.
+///
+/// The collector is stop-the-world and does not need to track writes, so this is a no-op.
+#[inline(always)]
+pub fn write_barrier(slot: *const u8) {
+    let _ = slot;
+}
+
+/// Write barrier for code that mutates objects without cells (i.e from C). See [write_barrier].
+#[no_mangle]
+pub extern "C" fn immix_write_barrier(slot: *const u8) {
+    write_barrier(slot)
+}
+
+/// Mutable memory location for `Copy` values.
+pub struct GcCell<T: Copy> {
+    value: Cell<T>,
+}
+
+impl<T: Copy> GcCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: Cell::new(value),
+        }
+    }
+
+    pub fn get(&self) -> T {
+        self.value.get()
+    }
+
+    pub fn set(&self, value: T) {
+        write_barrier(self.value.as_ptr().cast());
+        self.value.set(value);
+    }
+
+    /// Replace contained value and return the old one.
+    pub fn replace(&self, value: T) -> T {
+        write_barrier(self.value.as_ptr().cast());
+        self.value.replace(value)
+    }
+}
+
+impl<T: Copy + Trace> Trace for GcCell<T> {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        self.value.get_mut().trace(tracer);
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for GcCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GcCell")
+            .field("value", &self.get())
+            .finish()
+    }
+}
+
+/// Number of active shared borrows or `-1` if value is mutably borrowed.
+type BorrowFlag = isize;
+
+const WRITING: BorrowFlag = -1;
+
+/// Mutable memory location with dynamically checked borrow rules.
+///
+/// GC traces the value even if it is borrowed: collection happens only at yieldpoints and allocations where
+/// references into GC heap held in borrowed value may be updated.
+pub struct GcRefCell<T> {
+    borrow: Cell<BorrowFlag>,
+    value: UnsafeCell<T>,
+}
+
+/// Error returned by [GcRefCell::try_borrow] and [GcRefCell::try_borrow_mut] when value is already borrowed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BorrowError;
+
+impl<T> GcRefCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            borrow: Cell::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn try_borrow(&self) -> Result<GcRef<'_, T>, BorrowError> {
+        let borrow = self.borrow.get();
+        if borrow == WRITING {
+            return Err(BorrowError);
+        }
+        self.borrow.set(borrow + 1);
+        Ok(GcRef { cell: self })
+    }
+
+    /// Mutably borrow the value. [write_barrier] is invoked once for the borrow.
+    pub fn try_borrow_mut(&self) -> Result<GcRefMut<'_, T>, BorrowError> {
+        if self.borrow.get() != 0 {
+            return Err(BorrowError);
+        }
+        self.borrow.set(WRITING);
+        write_barrier(self.value.get().cast());
+        Ok(GcRefMut { cell: self })
+    }
+
+    /// Immutably borrow the value. Panics if value is mutably borrowed.
+    pub fn borrow(&self) -> GcRef<'_, T> {
+        self.try_borrow()
+            .expect("GcRefCell already mutably borrowed")
+    }
+
+    /// Mutably borrow the value. Panics if value is borrowed.
+    pub fn borrow_mut(&self) -> GcRefMut<'_, T> {
+        self.try_borrow_mut().expect("GcRefCell already borrowed")
+    }
+
+    /// Replace contained value and return the old one. Panics if value is borrowed.
+    pub fn replace(&self, value: T) -> T {
+        core::mem::replace(&mut *self.borrow_mut(), value)
+    }
+}
+
+impl<T: Trace> Trace for GcRefCell<T> {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        self.value.get_mut().trace(tracer);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for GcRefCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_borrow() {
+            Ok(value) => f.debug_struct("GcRefCell").field("value", &*value).finish(),
+            Err(_) => f
+                .debug_struct("GcRefCell")
+                .field("value", &"<borrowed>")
+                .finish(),
+        }
+    }
+}
+
+/// Shared borrow of [GcRefCell] value.
+pub struct GcRef<'a, T> {
+    cell: &'a GcRefCell<T>,
+}
+
+impl<T> Deref for GcRef<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T> Drop for GcRef<'_, T> {
+    fn drop(&mut self) {
+        self.cell.borrow.set(self.cell.borrow.get() - 1);
+    }
+}
+
+/// Mutable borrow of [GcRefCell] value.
+pub struct GcRefMut<'a, T> {
+    cell: &'a GcRefCell<T>,
+}
+
+impl<T> Deref for GcRefMut<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T> DerefMut for GcRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.cell.value.get() }
+    }
+}
+
+impl<T> Drop for GcRefMut<'_, T> {
+    fn drop(&mut self) {
+        self.cell.borrow.set(0);
+    }
+}
@@ -1,13 +1,179 @@
 use super::{allocation::ImmixSpace, block::ImmixBlock, constants::*, CollectionType};
-use crate::{large_object_space::LargeObjectSpace, object::*, util::*};
+use crate::{
+    large_object_space::{LargeObjectSpace, PreciseAllocation},
+    medium_object_space::MediumObjectSpace,
+    object::*,
+    util::*,
+};
 use alloc::collections::VecDeque;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
 use vec_map::VecMap;
 
 pub struct ImmixCollector;
+
+/// Result of marking.
+pub struct MarkResult {
+    /// Number of bytes in live objects in Immix space and medium object space. Large objects are accounted by
+    /// `LargeObjectSpace`.
+    pub visited: usize,
+    /// Number of bytes freed in medium object space.
+    pub medium_freed: usize,
+    /// Number of bytes freed in large object space.
+    pub large_freed: usize,
+    /// Number of live objects that are scanned conservatively.
+    pub conservative_objects: usize,
+    /// Objects found by conservative scanning of heap objects, they must be unpinned after collection.
+    pub pinned: Vec<*mut RawGc>,
+}
+
+/// If set `find_object` also recognizes pointers into the middle of objects.
+pub static INTERIOR_POINTERS: AtomicBool = AtomicBool::new(false);
+
+/// Return true if `ptr` that is `offset` bytes after the header of `object` points to the object itself: to its
+/// header, to its value, to the value after the metadata word of dynamically sized object or to the over-aligned
+/// value that is preceded by its distance from the header.
+///
+/// # Safety
+/// `object` must be allocated.
+unsafe fn points_to_object(object: *mut RawGc, ptr: *mut u8, offset: usize) -> bool {
+    let header_size = core::mem::size_of::<RawGc>();
+    if offset == 0 {
+        return true;
+    }
+    if offset > (*object).object_size() {
+        return false;
+    }
+    offset == header_size
+        || offset == header_size + 8
+        || (offset > header_size + 8 && ptr.cast::<usize>().sub(1).read() == offset)
+}
+
+/// Find object in Immix space that `ptr` points to. Each possible distance of `ptr` from the header is tried.
+///
+/// # Safety
+/// Spaces must be in consistent state i.e world is stopped.
+unsafe fn find_immix_object(immix_space: &ImmixSpace, ptr: *mut u8) -> Option<*mut RawGc> {
+    if !immix_space.filter_fast(Address::from_ptr(ptr)) {
+        return None;
+    }
+    let header_size = core::mem::size_of::<RawGc>();
+    let candidate = |offset: usize| -> Option<*mut RawGc> {
+        let object = immix_space
+            .filter(Address::from_ptr(ptr.wrapping_sub(offset)))?
+            .to_mut_ptr::<RawGc>();
+        if points_to_object(object, ptr, offset) {
+            Some(object)
+        } else {
+            None
+        }
+    };
+    for &offset in [0, header_size, header_size + 8].iter() {
+        if let Some(object) = candidate(offset) {
+            return Some(object);
+        }
+    }
+    // over-aligned value is aligned to at least 16 bytes
+    let mut align = 16;
+    while align <= MAX_ALIGNMENT && ptr as usize % align == 0 {
+        if let Some(object) = candidate(align_usize(header_size + 8, align)) {
+            return Some(object);
+        }
+        align *= 2;
+    }
+    None
+}
+
+/// Find object that `ptr` points to. `ptr` might point either to object header or to object value (see
+/// [GcPointee]) or, if `INTERIOR_POINTERS` is set, anywhere inside of object.
+///
+/// # Safety
+/// Spaces must be in consistent state i.e world is stopped.
+pub unsafe fn find_object(
+    immix_space: &ImmixSpace,
+    medium_object_space: &MediumObjectSpace,
+    large_object_space: &LargeObjectSpace,
+    ptr: *mut u8,
+) -> Option<*mut RawGc> {
+    if ptr.is_null() {
+        return None;
+    }
+    if PreciseAllocation::is_precise(ptr.cast())
+        && large_object_space.contains(Address::from_ptr(ptr))
+    {
+        return Some(ptr.cast());
+    }
+    if let Some(object) = find_immix_object(immix_space, ptr) {
+        return Some(object);
+    }
+    if medium_object_space.in_space(Address::from_ptr(ptr)) {
+        let object = medium_object_space.find_interior(Address::from_ptr(ptr))?;
+        let offset = ptr as usize - object as usize;
+        if points_to_object(object, ptr, offset) || INTERIOR_POINTERS.load(Ordering::Relaxed) {
+            return Some(object);
+        }
+        return None;
+    }
+    if let Some(object) = large_object_space.find_interior(Address::from_ptr(ptr)) {
+        let offset = ptr as usize - object as usize;
+        if points_to_object(object, ptr, offset) || INTERIOR_POINTERS.load(Ordering::Relaxed) {
+            return Some(object);
+        }
+        return None;
+    }
+    if INTERIOR_POINTERS.load(Ordering::Relaxed) {
+        return immix_space
+            .find_interior(Address::from_ptr(ptr))
+            .map(|object| object.to_mut_ptr());
+    }
+    None
+}
+
+/// Return true if `object` does not contain references and should not be scanned.
+pub(crate) unsafe fn is_no_scan(
+    immix_space: &ImmixSpace,
+    medium_object_space: &MediumObjectSpace,
+    object: *mut RawGc,
+) -> bool {
+    let addr = Address::from_ptr(object);
+    if immix_space.filter_fast(addr) {
+        immix_space.is_no_scan(addr)
+    } else if medium_object_space.in_space(addr) {
+        medium_object_space.is_no_scan(addr)
+    } else {
+        (*PreciseAllocation::from_cell(object)).no_scan
+    }
+}
+
+/// Mark `object` and, if it is in immix space, its line and bit in object bitmap or, if it is in medium object
+/// space, its bit in mark bitmap.
+///
+/// Returns false if object was already marked.
+unsafe fn mark_object(
+    immix_space: &mut ImmixSpace,
+    medium_object_space: &mut MediumObjectSpace,
+    object: *mut RawGc,
+    next_live_mark: bool,
+) -> bool {
+    if (&mut *object).mark(next_live_mark) {
+        return false;
+    }
+    let object_addr = Address::from_ptr(object);
+    if immix_space.filter_fast(object_addr) {
+        let block = ImmixBlock::get_block_ptr(object_addr);
+        immix_space.set_gc_object(object_addr); // Mark object in bitmap
+        (&mut *block).line_object_mark(object_addr); // Mark block line
+    } else if medium_object_space.in_space(object_addr) {
+        medium_object_space.mark(object_addr);
+    }
+    true
+}
+
 pub struct Visitor<'a> {
     immix_space: &'a mut ImmixSpace,
+    medium_object_space: &'a mut MediumObjectSpace,
     queue: &'a mut VecDeque<*mut RawGc>,
+    visited: &'a mut usize,
     defrag: bool,
     next_live_mark: bool,
 }
@@ -29,8 +195,21 @@ impl<'a> Tracer for Visitor<'a> {
                         child = &mut *new_child.to_mut_ptr::<RawGc>();
                     }
                 }
-                debug!("Push child {:p} into object queue", child);
-                self.queue.push_back(child);
+                if is_no_scan(self.immix_space, self.medium_object_space, child) {
+                    // Objects without references are marked right away and never pushed to queue.
+                    if mark_object(
+                        self.immix_space,
+                        self.medium_object_space,
+                        child,
+                        self.next_live_mark,
+                    ) && !child.is_precise_allocation()
+                    {
+                        *self.visited += child.object_size();
+                    }
+                } else {
+                    debug!("Push child {:p} into object queue", child);
+                    self.queue.push_back(child);
+                }
             }
         }
     }
@@ -42,8 +221,10 @@ impl ImmixCollector {
         roots: &[*mut RawGc],
         precise_roots: &[*mut *mut RawGc],
         immix_space: &mut ImmixSpace,
+        medium_object_space: &mut MediumObjectSpace,
+        large_object_space: &LargeObjectSpace,
         next_live_mark: bool,
-    ) -> usize {
+    ) -> MarkResult {
         let mut object_queue: VecDeque<*mut RawGc> = roots.iter().copied().collect();
         for root in precise_roots.iter() {
             unsafe {
@@ -64,25 +245,29 @@ impl ImmixCollector {
             }
         }
         let mut visited = 0;
+        let mut conservative_objects = 0;
+        let mut pinned = Vec::new();
+        let tag_mask = crate::CONSERVATIVE_TAG_MASK.load(Ordering::Relaxed);
 
         while let Some(object) = object_queue.pop_front() {
             unsafe {
                 //debug!("Process object {:p} in Immix closure", object);
-                let object_addr = Address::from_ptr(object);
-                if !(&mut *object).mark(next_live_mark) {
-                    if immix_space.filter_fast(object_addr) {
-                        let block = ImmixBlock::get_block_ptr(object_addr);
-                        immix_space.set_gc_object(object_addr); // Mark object in bitmap
-                        (&mut *block).line_object_mark(object_addr); // Mark block line
+                if mark_object(immix_space, medium_object_space, object, next_live_mark) {
+                    if !(&*object).is_precise_allocation() {
+                        visited += (&*object).object_size();
+                    }
+                    if is_no_scan(immix_space, medium_object_space, object) {
+                        continue;
                     }
                     debug!("Object {:p} was unmarked: visit their children", object);
-                    visited += (&*object).object_size();
                     let visitor_fn = (&*object).rtti().visit_references;
                     {
                         let mut visitor = core::mem::transmute::<_, Visitor<'static>>(Visitor {
                             immix_space,
+                            medium_object_space,
                             next_live_mark,
                             queue: &mut object_queue,
+                            visited: &mut visited,
                             defrag: *collection_type == CollectionType::ImmixEvacCollection,
                         });
 
@@ -93,11 +278,43 @@ impl ImmixCollector {
                             },
                         );
                     }
+                    if (&*object).rtti().conservative {
+                        conservative_objects += 1;
+                        // identity hash slot of moved object is not scanned
+                        let size = ((&*object).rtti().heap_size)(object as *mut u8);
+                        let mut scan = object.cast::<usize>().add(1);
+                        let end = object.cast::<u8>().add(size).cast::<usize>();
+                        while scan < end {
+                            let ptr = (scan.read_unaligned() & !tag_mask) as *mut u8;
+                            if let Some(child) = find_object(
+                                immix_space,
+                                medium_object_space,
+                                large_object_space,
+                                ptr,
+                            ) {
+                                debug!("Found conservative reference {:p} in {:p}", child, object);
+                                if !(&*child).is_pinned() {
+                                    (&mut *child).pin();
+                                    pinned.push(child);
+                                }
+                                if (&*child).get_mark() != next_live_mark {
+                                    object_queue.push_back(child);
+                                }
+                            }
+                            scan = scan.add(1);
+                        }
+                    }
                 }
             }
         }
         debug!("Completed collection with {} bytes visited", visited);
-        visited
+        MarkResult {
+            visited,
+            medium_freed: 0,
+            large_freed: 0,
+            conservative_objects,
+            pinned,
+        }
     }
 }
 use alloc::vec::Vec;
@@ -184,25 +401,45 @@ impl Collector {
         roots: &[*mut RawGc],
         precise_roots: &[*mut *mut RawGc],
         immix_space: &mut ImmixSpace,
+        medium_object_space: &mut MediumObjectSpace,
         large_object_space: &mut LargeObjectSpace,
         next_live_mark: bool,
-    ) -> usize {
+        conservative: bool,
+    ) -> MarkResult {
         // TODO: maybe use immix_space.bitmap.clear_range(immix_space.begin,immix_space.block_cursor)?
         for block in &mut self.all_blocks {
             unsafe {
-                immix_space
-                    .bitmap
-                    .clear_range((*block) as usize, (*block) as usize + BLOCK_SIZE);
+                // Conservatively scanned objects use object bitmap to find their children so
+                // bits of dead objects are cleared after marking.
+                if !conservative {
+                    immix_space
+                        .bitmap
+                        .clear_range((*block) as usize, (*block) as usize + BLOCK_SIZE);
+                }
                 (**block).line_map.clear_all();
             }
         }
-        let visited = ImmixCollector::collect(
+        let result = ImmixCollector::collect(
             collection_type,
             roots,
             precise_roots,
             immix_space,
+            medium_object_space,
+            large_object_space,
             next_live_mark,
         );
+        if conservative {
+            for &block in &self.all_blocks {
+                let begin = block as usize;
+                immix_space
+                    .bitmap
+                    .visit_marked_range(begin, begin + BLOCK_SIZE, |object| unsafe {
+                        if (*(object as *mut RawGc)).get_mark() != next_live_mark {
+                            immix_space.bitmap.clear(object);
+                        }
+                    });
+            }
+        }
         self.mark_histogram.clear();
         let (recyclable_blocks, free_blocks) = self.sweep_all_blocks();
         immix_space.set_recyclable_blocks(recyclable_blocks);
@@ -216,8 +453,13 @@ impl Collector {
         };
         immix_space.extend_evac_headroom(free_blocks.iter().take(evac_headroom).copied());
         immix_space.return_blocks(free_blocks.iter().skip(evac_headroom).copied());
-        large_object_space.sweep();
-        visited
+        let medium_freed = medium_object_space.sweep();
+        let large_freed = large_object_space.sweep(next_live_mark);
+        MarkResult {
+            medium_freed,
+            large_freed,
+            ..result
+        }
     }
     /// Sweep all blocks in the buffer after the collection.
     ///
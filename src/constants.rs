@@ -7,8 +7,19 @@ pub const NUM_LINES_PER_BLOCK: usize = BLOCK_SIZE / LINE_SIZE;
 /// `NormalAllocator`, otherwise the `OverflowAllocator` is used.
 pub const MEDIUM_OBJECT: usize = LINE_SIZE;
 
-/// Objects larger than LARGE_OBJECT are allocated using the `LargeObjectSpace`.
+/// Objects of LARGE_OBJECT bytes or more are allocated using the `MediumObjectSpace` or `LargeObjectSpace`.
 pub const LARGE_OBJECT: usize = 8 * 1024;
+/// Objects larger than MEDIUM_OBJECT_LIMIT are allocated using the `LargeObjectSpace`.
+pub const MEDIUM_OBJECT_LIMIT: usize = 256 * 1024;
+/// Address space reserved for the `LargeObjectSpace`. Memory is committed only for allocated objects.
+#[cfg(target_pointer_width = "64")]
+pub const LARGE_OBJECT_SPACE_SIZE: usize = 64 * 1024 * 1024 * 1024;
+#[cfg(not(target_pointer_width = "64"))]
+pub const LARGE_OBJECT_SPACE_SIZE: usize = 1024 * 1024 * 1024;
+/// Number of contiguous blocks in one chunk of the `MediumObjectSpace`.
+pub const MEDIUM_CHUNK_BLOCKS: usize = 16;
+/// Maximal alignment supported by `immix_alloc_aligned` and `immix_alloc_safe`.
+pub const MAX_ALIGNMENT: usize = 4096;
 /// Whether evacuation should be used or not.
 pub const USE_EVACUATION: bool = true;
 
@@ -17,3 +28,6 @@ pub const EVAC_HEADROOM: usize = 5;
 
 /// Ratio when to trigger evacuation collection.
 pub const EVAC_TRIGGER_THRESHHOLD: f64 = 0.25;
+
+/// Number of GC cycles a line stays blacklisted after a false conservative pointer into it was found.
+pub const BLACKLIST_CYCLES: u8 = 3;
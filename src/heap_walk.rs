@@ -0,0 +1,104 @@
+//! Heap walking.
+//!
+//! [immix_walk_heap] and [heap_objects] enumerate every allocated object in Immix space, medium object space and
+//! large object space.
+//! Objects allocated since the last GC cycle are reported too even if they are already unreachable.
+//! Mutator threads are stopped while heap is walked so objects are not moved or freed, the walking thread must
+//! not allocate or trigger GC until walking is finished.
+use crate::{object::*, GCObject, Immix, StoppedWorld, SPACE};
+use alloc::vec::Vec;
+
+/// Allocated object found by heap walker.
+#[derive(Clone, Copy)]
+pub struct HeapObjectInfo {
+    /// Pointer to object header.
+    pub object: *mut RawGc,
+    pub rtti: &'static GCRTTI,
+    /// Size of the object on the heap including header.
+    pub size: usize,
+}
+
+pub type HeapWalkCallback =
+    extern "C" fn(data: *mut u8, object: *mut GCObject, rtti: *const GCRTTI, size: usize);
+
+impl Immix {
+    /// Invoke `visitor` on every allocated object. Must only be called while the world is stopped.
+    pub(crate) unsafe fn walk_heap(&self, mut visitor: impl FnMut(HeapObjectInfo)) {
+        let immix = &*self.immix;
+        let block_allocator = &*immix.block_allocator;
+        let mut visit = |object: *mut RawGc| {
+            visitor(HeapObjectInfo {
+                object,
+                rtti: (*object).rtti(),
+                size: (*object).object_size(),
+            })
+        };
+        immix.bitmap.visit_marked_range(
+            block_allocator.mmap.aligned() as usize,
+            block_allocator.data as usize,
+            |object| visit(object as *mut RawGc),
+        );
+        self.mos.visit_objects(&mut visit);
+        for &allocation in self.los.allocations.iter() {
+            visit((*allocation).cell());
+        }
+    }
+}
+
+/// Invoke `callback` on every allocated object with its RTTI and size. Other threads are stopped while heap is
+/// walked, `callback` must not allocate or trigger GC.
+#[no_mangle]
+pub extern "C" fn immix_walk_heap(callback: HeapWalkCallback, data: *mut u8) {
+    unsafe {
+        let world = crate::stop_the_world_retry();
+        (*SPACE).walk_heap(|info| {
+            callback(
+                data,
+                info.object.cast(),
+                info.rtti as *const GCRTTI,
+                info.size,
+            )
+        });
+        crate::resume_the_world(world);
+    }
+}
+
+/// Iterator over all allocated objects returned by [heap_objects]. Other threads are stopped until it is dropped.
+pub struct HeapObjects {
+    objects: alloc::vec::IntoIter<HeapObjectInfo>,
+    world: Option<StoppedWorld>,
+}
+
+impl Iterator for HeapObjects {
+    type Item = HeapObjectInfo;
+    fn next(&mut self) -> Option<HeapObjectInfo> {
+        self.objects.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.objects.size_hint()
+    }
+}
+
+impl Drop for HeapObjects {
+    fn drop(&mut self) {
+        if let Some(world) = self.world.take() {
+            unsafe {
+                crate::resume_the_world(world);
+            }
+        }
+    }
+}
+
+/// Stop the world and return iterator over all allocated objects. The world is resumed when iterator is dropped,
+/// until then current thread must not allocate or trigger GC.
+pub fn heap_objects() -> HeapObjects {
+    unsafe {
+        let world = crate::stop_the_world_retry();
+        let mut objects = Vec::new();
+        (*SPACE).walk_heap(|info| objects.push(info));
+        HeapObjects {
+            objects: objects.into_iter(),
+            world: Some(world),
+        }
+    }
+}
@@ -1,38 +1,41 @@
+use crate::block_allocator::Mmap;
 use crate::object::*;
 use crate::util::*;
-use core::alloc::Layout;
-use core::sync::atomic::AtomicBool;
-use core::{alloc::Allocator, ptr::NonNull};
+use alloc::vec::Vec;
 /// Precise allocation used for large objects (>= LARGE_CUTOFF).
-/// Wafflelink uses GlobalAlloc that already knows what to do for large allocations. The GC shouldn't
-/// have to think about such things. That's where PreciseAllocation comes in. We will allocate large
-/// objects directly using malloc, and put the PreciseAllocation header just before them. We can detect
-/// when a *mut Object is a PreciseAllocation because it will have the ATOM_SIZE / 2 bit set.
+/// Each large object gets its own run of pages in the region reserved by `LargeObjectSpace` and the
+/// PreciseAllocation header is put just before it. We can detect when a *mut Object is a PreciseAllocation
+/// because it will have the ATOM_SIZE / 2 bit set.
+///
+/// Large objects are marked with the mark bit in their `RawGc` header like any other object, liveness is
+/// decided from it when the space is swept.
 #[repr(C)]
 pub struct PreciseAllocation {
     //pub link: LinkedListLink,
     /// allocation request size
     pub cell_size: usize,
-    /// Is this allocation marked by GC?
-    pub is_marked: bool,
-    /// index in precise_allocations
-    pub index_in_space: u32,
-    /// Is alignment adjusted?
     //pub is_newly_allocated: bool,
-    pub adjusted_alignment: bool,
+    /// Distance from the start of allocated memory to this header.
+    pub base_offset: u32,
+    /// Number of pages of allocated memory.
+    pub pages: u32,
     /// Is this even valid allocation?
     pub has_valid_cell: bool,
+    /// Object does not contain references and is never scanned.
+    pub no_scan: bool,
 }
 
 impl PreciseAllocation {
-    /// Return atomic reference to `is_marked`
-    pub fn mark_atomic(&self) -> &AtomicBool {
-        unsafe { &*(&self.is_marked as *const bool as *const AtomicBool) }
-    }
     /// Alignment of allocation.
     pub const ALIGNMENT: usize = 16;
     /// Alignment of pointer returned by `Self::cell`.
     pub const HALF_ALIGNMENT: usize = Self::ALIGNMENT / 2;
+    /// Distance from the cell to value of object with alignment above `HALF_ALIGNMENT`: the first address after
+    /// the object header and one word that is aligned to `ALIGNMENT`.
+    pub const ALIGNED_VALUE_OFFSET: usize =
+        ((core::mem::size_of::<RawGc>() + 8 + Self::HALF_ALIGNMENT + Self::ALIGNMENT - 1)
+            & !(Self::ALIGNMENT - 1))
+            - Self::HALF_ALIGNMENT;
     /// Check if raw_ptr is precisely allocated.
     pub fn is_precise(raw_ptr: *mut ()) -> bool {
         (raw_ptr as usize & Self::HALF_ALIGNMENT) != 0
@@ -48,31 +51,8 @@ impl PreciseAllocation {
     /// Return base pointer
     #[inline]
     pub fn base_pointer(&self) -> *mut () {
-        if self.adjusted_alignment {
-            ((self as *const Self as isize) - (Self::HALF_ALIGNMENT as isize)) as *mut ()
-        } else {
-            self as *const Self as *mut ()
-        }
-    }
-    /// Set `is_marked` to false
-    pub fn clear_marked(&mut self) {
-        self.is_marked = false;
-        //self.is_marked.store(false, Ordering::Relaxed);
-    }
-    /// Return `is_marked`
-    pub fn is_marked(&self) -> bool {
-        self.is_marked
+        ((self as *const Self as usize) - self.base_offset as usize) as *mut ()
     }
-    /// Test and set marked. Will return true
-    /// if it is already marked.
-    pub fn test_and_set_marked(&mut self) -> bool {
-        if self.is_marked() {
-            return true;
-        }
-        self.is_marked = true;
-        false
-    }
-
     /// Return cell address, it is always aligned to `Self::HALF_ALIGNMENT`.
     pub fn cell(&self) -> *mut RawGc {
         let addr = Address::from_ptr(self).offset(Self::header_size());
@@ -101,21 +81,13 @@ impl PreciseAllocation {
     pub fn contains(&self, raw_ptr: *mut ()) -> bool {
         self.above_lower_bound(raw_ptr) && self.below_upper_bound(raw_ptr)
     }
-    /// Is this allocation live?
-    pub fn is_live(&self) -> bool {
-        self.is_marked() //|| self.is_newly_allocated
+    /// Is this allocation live? Object is live if its header is marked with `live_mark`.
+    pub fn is_live(&self, live_mark: bool) -> bool {
+        unsafe { (*self.cell()).get_mark() == live_mark }
     }
-    /// Clear mark bit
-    pub fn flip(&mut self) {
-        self.clear_marked();
-    }
-    /// Is this marked?
-    pub fn is_empty(&self) -> bool {
-        !self.is_marked() //&& !self.is_newly_allocated
-    }
-    /// Derop cell if this allocation is not marked.
-    pub fn sweep(&mut self) -> bool {
-        if self.has_valid_cell && !self.is_live() {
+    /// Drop cell if this allocation is not marked with `live_mark`.
+    pub fn sweep(&mut self, live_mark: bool) -> bool {
+        if self.has_valid_cell && !self.is_live(live_mark) {
             self.has_valid_cell = false;
             let cell = self.cell();
             unsafe {
@@ -127,60 +99,42 @@ impl PreciseAllocation {
         }
         true
     }
-    /// Try to create precise allocation (no way that it will return null for now).
-    pub fn try_create(size: usize, index_in_space: u32) -> *mut Self {
-        let adjusted_alignment_allocation_size = Self::header_size() + size + Self::HALF_ALIGNMENT;
-        unsafe {
-            let mut space = LibcAlloc
-                .allocate(
-                    Layout::from_size_align(
-                        adjusted_alignment_allocation_size,
-                        Self::HALF_ALIGNMENT,
-                    )
-                    .unwrap(),
-                )
-                .unwrap()
-                .cast::<u8>()
-                .as_ptr();
-            //let mut space = libc::malloc(adjusted_alignment_allocation_size);
-            let mut adjusted_alignment = false;
-            if !is_aligned_for_precise_allocation(space) {
-                space = space.add(Self::HALF_ALIGNMENT);
-                adjusted_alignment = true;
-                assert!(is_aligned_for_precise_allocation(space));
-            }
-            assert!(size != 0);
-            space.cast::<Self>().write(Self {
-                //link: LinkedListLink::new(),
-                adjusted_alignment,
-                is_marked: false,
-                //is_newly_allocated: true,
-                has_valid_cell: true,
-                cell_size: size,
-                index_in_space,
-            });
-
-            assert!((&*(&*space.cast::<Self>()).cell()).is_precise_allocation());
-            space.cast()
+    /// Number of bytes to allocate for object of `size` bytes with alignment `align`.
+    pub fn allocation_size(size: usize, align: usize) -> usize {
+        Self::header_size() + size + core::cmp::max(align, Self::HALF_ALIGNMENT)
+    }
+    /// Create precise allocation in `pages` pages at `base` that are at least `Self::allocation_size` bytes
+    /// long. If `align` is above `Self::HALF_ALIGNMENT` memory at `Self::ALIGNED_VALUE_OFFSET` from the cell is
+    /// aligned to it.
+    ///
+    /// # Safety
+    /// `base` must be aligned to `align` and `Self::ALIGNMENT`.
+    pub unsafe fn create(base: *mut u8, pages: usize, size: usize, align: usize) -> *mut Self {
+        let mut space = base;
+        if align > Self::HALF_ALIGNMENT {
+            // value offset is `HALF_ALIGNMENT` away from alignment, `space` stays aligned to `ALIGNMENT`.
+            let value_offset = Self::header_size() + Self::ALIGNED_VALUE_OFFSET;
+            space = (align_usize(base as usize + value_offset, align) - value_offset) as *mut u8;
         }
+        assert!(is_aligned_for_precise_allocation(space));
+        assert!(size != 0);
+        space.cast::<Self>().write(Self {
+            //link: LinkedListLink::new(),
+            base_offset: (space as usize - base as usize) as u32,
+            pages: pages as u32,
+            //is_newly_allocated: true,
+            has_valid_cell: true,
+            cell_size: size,
+            no_scan: false,
+        });
+
+        assert!((&*(&*space.cast::<Self>()).cell()).is_precise_allocation());
+        space.cast()
     }
     /// return cell size
     pub fn cell_size(&self) -> usize {
         self.cell_size
     }
-    /// Destroy this allocation
-    pub fn destroy(&mut self) {
-        let adjusted_alignment_allocation_size =
-            Self::header_size() + self.cell_size + Self::HALF_ALIGNMENT;
-        let base = self.base_pointer();
-        unsafe {
-            LibcAlloc.deallocate(
-                NonNull::new_unchecked(base.cast::<u8>()),
-                Layout::from_size_align(adjusted_alignment_allocation_size, Self::ALIGNMENT)
-                    .unwrap(),
-            );
-        }
-    }
 }
 /// Check if `mem` is aligned for precise allocation
 pub fn is_aligned_for_precise_allocation(mem: *mut u8) -> bool {
@@ -188,74 +142,180 @@ pub fn is_aligned_for_precise_allocation(mem: *mut u8) -> bool {
     (allocable_ptr & (PreciseAllocation::ALIGNMENT - 1)) == 0
 }
 /// This space contains objects which are larger than the size limits of other spaces.
-/// Each object gets its own malloc'd region of memory.
+/// Address space for large objects is reserved up front and memory is committed only for allocated objects. Each
+/// object gets its own run of pages in the reservation and a side table maps every page below the highest allocated
+/// one to the allocation that covers it, so membership and object lookup are O(1).
 /// Large objects are never moved by the garbage collector.
 pub struct LargeObjectSpace {
-    pub(crate) allocations: alloc::vec::Vec<*mut PreciseAllocation>,
+    /// Live allocations in no particular order.
+    pub(crate) allocations: Vec<*mut PreciseAllocation>,
     pub(crate) current_live_mark: bool,
-}
-
-impl Default for LargeObjectSpace {
-    fn default() -> Self {
-        Self::new()
-    }
+    mmap: Mmap,
+    /// Start of the reservation aligned to page size.
+    start: usize,
+    page_size: usize,
+    /// Number of pages in the reservation.
+    limit: usize,
+    /// Allocation that covers each page below the first page that was never allocated, or null if page is free.
+    page_table: Vec<*mut PreciseAllocation>,
+    /// Runs of free pages in `page_table` as `(first page, number of pages)`.
+    free_runs: Vec<(usize, usize)>,
+    /// Number of bytes in allocated objects.
+    live_bytes: usize,
 }
 
 impl LargeObjectSpace {
-    pub fn new() -> Self {
+    /// Reserve `size` bytes of address space for large objects.
+    pub fn new(size: usize) -> Self {
+        let page_size = *crate::PAGESIZE;
+        let size = align_usize(size, page_size);
+        let mmap = Mmap::reserve(size + page_size).expect("failed to reserve large object space");
+        let start = align_usize(mmap.start() as usize, page_size);
+        debug!(
+            "New large object space from 0x{:x} to 0x{:x}",
+            start,
+            start + size
+        );
         Self {
             current_live_mark: false,
-            allocations: alloc::vec::Vec::with_capacity(8),
+            allocations: Vec::with_capacity(8),
+            mmap,
+            start,
+            page_size,
+            limit: size / page_size,
+            page_table: Vec::new(),
+            free_runs: Vec::new(),
+            live_bytes: 0,
+        }
+    }
+    /// Return allocation that covers `p` or null.
+    fn allocation_at(&self, p: usize) -> *mut PreciseAllocation {
+        if p < self.start {
+            return core::ptr::null_mut();
         }
+        match self.page_table.get((p - self.start) / self.page_size) {
+            Some(&allocation) => allocation,
+            None => core::ptr::null_mut(),
+        }
+    }
+    /// Return number of bytes in objects allocated in this space, dead objects are counted until they are swept.
+    pub fn live_bytes(&self) -> usize {
+        self.live_bytes
     }
-    pub fn sweep(&mut self) -> usize {
+    /// Find `pages` free pages, first fit. Returns `None` if the reservation is exhausted.
+    fn allocate_pages(&mut self, pages: usize) -> Option<usize> {
+        if let Some(ix) = self.free_runs.iter().position(|&(_, len)| len >= pages) {
+            let (first, len) = self.free_runs[ix];
+            if len == pages {
+                self.free_runs.swap_remove(ix);
+            } else {
+                self.free_runs[ix] = (first + pages, len - pages);
+            }
+            return Some(first);
+        }
+        let first = self.page_table.len();
+        if first + pages > self.limit {
+            return None;
+        }
+        self.page_table.resize(first + pages, core::ptr::null_mut());
+        Some(first)
+    }
+    /// Release memory of `allocation` and clear its pages in the page table.
+    unsafe fn free(&mut self, allocation: *mut PreciseAllocation) {
+        let base = (*allocation).base_pointer() as usize;
+        let pages = (*allocation).pages as usize;
+        let first = (base - self.start) / self.page_size;
+        for entry in &mut self.page_table[first..first + pages] {
+            *entry = core::ptr::null_mut();
+        }
+        self.mmap.dontneed(base as *mut u8, pages * self.page_size);
+        self.free_runs.push((first, pages));
+    }
+    /// Merge adjacent free runs and drop the last run from the page table.
+    fn coalesce_free_runs(&mut self) {
+        self.free_runs.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(self.free_runs.len());
+        for &(first, len) in self.free_runs.iter() {
+            match merged.last_mut() {
+                Some(last) if last.0 + last.1 == first => last.1 += len,
+                _ => merged.push((first, len)),
+            }
+        }
+        if let Some(&(first, len)) = merged.last() {
+            if first + len == self.page_table.len() {
+                self.page_table.truncate(first);
+                merged.pop();
+            }
+        }
+        self.free_runs = merged;
+    }
+    /// Free objects that are not marked with `live_mark` and return number of freed bytes.
+    pub fn sweep(&mut self, live_mark: bool) -> usize {
         let mut sweeped = 0;
+        let mut dead = Vec::new();
         self.allocations.retain(|ptr| unsafe {
             let p = &mut **ptr;
-            let retain = p.sweep();
+            let retain = p.sweep(live_mark);
             if !retain {
-                p.destroy();
                 sweeped += p.cell_size;
+                dead.push(*ptr);
             }
             retain
         });
-        self.allocations.sort_unstable();
+        for allocation in dead {
+            unsafe {
+                self.free(allocation);
+            }
+        }
+        self.coalesce_free_runs();
+        self.live_bytes -= sweeped;
         sweeped
     }
-    #[allow(clippy::collapsible_if)]
-    pub fn contains(&self, p: Address) -> bool {
-        if self.allocations.is_empty() {
-            return false;
+    /// Find large object that contains `p`.
+    pub fn find_interior(&self, p: Address) -> Option<*mut RawGc> {
+        let allocation = self.allocation_at(p.to_usize());
+        if allocation.is_null() {
+            return None;
         }
         unsafe {
-            if (&*self.allocations[0]).above_lower_bound(p.to_mut_ptr())
-                && (&**self.allocations.last().unwrap()).below_upper_bound(p.to_mut_ptr())
-            {
-                if self
-                    .allocations
-                    .binary_search(&PreciseAllocation::from_cell(p.to_mut_ptr()))
-                    .is_ok()
-                {
-                    return true;
-                }
+            let cell = (*allocation).cell() as usize;
+            if p.to_usize() >= cell && p.to_usize() < cell + (*allocation).cell_size() {
+                Some(cell as *mut RawGc)
+            } else {
+                None
             }
         }
-        false
+    }
+    /// Return true if `p` points to a large object.
+    pub fn contains(&self, p: Address) -> bool {
+        let allocation = self.allocation_at(p.to_usize());
+        !allocation.is_null() && unsafe { (*allocation).cell() } == p.to_mut_ptr()
     }
 
-    pub fn alloc(&mut self, size: usize, vtable: usize) -> Address {
-        let ix = self.allocations.len() as u32;
-        let cell = PreciseAllocation::try_create(size, ix);
+    /// Allocate object of `size` bytes. If `align` is above 8 memory at `PreciseAllocation::ALIGNED_VALUE_OFFSET`
+    /// from the object is aligned to it.
+    ///
+    /// ## Return value
+    /// Returns null if there is not enough free pages left in the reservation.
+    pub fn alloc(&mut self, size: usize, vtable: usize, no_scan: bool, align: usize) -> Address {
+        let pages = align_usize(
+            PreciseAllocation::allocation_size(size, align),
+            self.page_size,
+        ) / self.page_size;
+        let first = match self.allocate_pages(pages) {
+            Some(first) => first,
+            None => return Address::null(),
+        };
         unsafe {
-            if cell.is_null() {
-                return Address::null();
+            let base = (self.start + first * self.page_size) as *mut u8;
+            self.mmap.commit(base, pages * self.page_size);
+            let cell = PreciseAllocation::create(base, pages, size, align);
+            (*cell).no_scan = no_scan;
+            for entry in &mut self.page_table[first..first + pages] {
+                *entry = cell;
             }
             self.allocations.push(cell);
-            if (cell as usize) < self.allocations[self.allocations.len() - 1] as usize
-                || (cell as usize) < self.allocations[0] as usize
-            {
-                self.allocations.sort_unstable();
-            }
+            self.live_bytes += size;
             let raw = (&*cell).cell();
             raw.write(RawGc::new(vtable));
             (&mut *raw).mark(self.current_live_mark);
@@ -263,13 +323,3 @@ impl LargeObjectSpace {
         }
     }
 }
-
-impl Drop for LargeObjectSpace {
-    fn drop(&mut self) {
-        while let Some(alloc) = self.allocations.pop() {
-            unsafe {
-                (&mut *alloc).destroy();
-            }
-        }
-    }
-}
@@ -7,7 +7,8 @@
     alloc_layout_extra,
     raw,
     linked_list_cursors,
-    thread_local
+    thread_local,
+    unsize
 )]
 #![cfg_attr(test, feature(const_in_array_repeat_expressions))]
 #![cfg_attr(not(test), no_std)]
@@ -19,10 +20,15 @@ extern crate log;
 use core::ptr::NonNull;
 
 use allocation::ImmixSpace;
-use constants::{BLOCK_SIZE, LARGE_OBJECT};
-use core::sync::atomic::Ordering;
-use large_object_space::LargeObjectSpace;
+use constants::{BLOCK_SIZE, LARGE_OBJECT, LARGE_OBJECT_SPACE_SIZE};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use large_object_space::{LargeObjectSpace, PreciseAllocation};
+use medium_object_space::MediumObjectSpace;
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+// Allows code generated by `#[derive(HeapObject)]` to refer to this crate by name.
+extern crate self as libimmixcons;
 
 #[no_mangle]
 pub extern "C" fn immix_enable_stats(val: GcStats) {
@@ -68,6 +74,19 @@ pub extern "C" fn immix_dump_summary() {
             b"GC stats: collections count=%i\n\0".as_ptr().cast(),
             stats.collections() as i32,
         );
+        #[cfg(unix)]
+        printf(
+            b"GC stats: medium objects freed=%lu\n\0".as_ptr().cast(),
+            stats.medium_freed as u64,
+        );
+        #[cfg(unix)]
+        printf(
+            b"GC stats: large objects freed=%lu live=%lu\n\0"
+                .as_ptr()
+                .cast(),
+            stats.large_freed as u64,
+            (*SPACE).los.live_bytes() as u64,
+        );
         #[cfg(unix)]printf(b"GC summary: %.1fms collection (%i), %.1fms mutator, %.1f total (%f%% mutator, %f%% GC)\n\0".as_ptr().cast(),stats.pause() as libc::c_double,stats.collections() as i32,stats.mutator(runtime) as libc::c_double,runtime as libc::c_double,mutator as libc::c_double,gc as libc::c_double);
     }
 }
@@ -75,22 +94,35 @@ pub extern "C" fn immix_dump_summary() {
 #[macro_use]
 pub(crate) mod util;
 pub mod allocation;
+pub mod array;
+#[cfg(feature = "bdwgc-compat")]
+pub mod bdwgc;
+pub mod blacklist;
 pub mod block;
 pub mod block_allocator;
+pub mod cell;
 pub mod collector;
 pub mod constants;
+pub mod heap_walk;
 pub(crate) mod large_object_space;
+pub(crate) mod medium_object_space;
 pub mod object;
+pub mod profiler;
+pub mod relocation;
+pub mod retention;
 #[cfg(feature = "threaded")]
 pub mod safepoint;
 pub mod signals;
+pub mod snapshot;
 pub mod space_bitmap;
 pub mod stack_bounds;
+pub mod stack_map;
+pub mod static_roots;
 pub mod threading;
+pub mod verify;
 use alloc::collections::LinkedList;
 use alloc::vec::Vec;
 use collector::Collector;
-use large_object_space::PreciseAllocation;
 use libc::malloc;
 #[cfg(feature = "threaded")]
 use locks::mutex::Mutex;
@@ -113,20 +145,36 @@ pub struct Immix {
     #[cfg(not(feature = "threaded"))]
     bounds: StackBounds,
     los: LargeObjectSpace,
+    mos: MediumObjectSpace,
     immix: *mut ImmixSpace,
     stack_bottom: *mut u8,
     gc_stats: GcStats,
     stack_end: *mut u8,
     allocated: usize,
     threshold: usize,
+    /// Large object space is collected when its live bytes reach this threshold.
+    large_threshold: usize,
+    /// Set when medium object space stays full after emergency GC. Medium objects are allocated in large object
+    /// space without another emergency GC until the next collection.
+    medium_exhausted: bool,
     current_live_mark: bool,
     collect_roots_callback: Vec<(CollectRootsCallback, *mut u8)>,
+    relocation_callbacks: Vec<(relocation::RelocationCallback, *mut u8)>,
+    stack_maps: stack_map::StackMaps,
+    static_roots: Vec<(usize, usize)>,
+    /// Conservative roots found in current GC cycle that did not point to any object.
+    false_roots: Vec<Address>,
+    /// Number of live objects that are scanned conservatively. Evacuation is disabled while it is not zero.
+    conservative_objects: usize,
+    #[cfg(feature = "threaded")]
+    roots_lock: Mutex,
     timer: util::timer::Timer,
     collector: Collector,
     to_finalize: LinkedList<*mut RawGc>,
     #[cfg(feature = "threaded")]
     fin_lock: Mutex,
     stats: CollectionStats,
+    profiler: profiler::Profiler,
 }
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum CollectionType {
@@ -143,6 +191,63 @@ fn stack_pointer() -> usize {
 pub type CollectRootsCallback =
     extern "C" fn(data: *mut u8, tracer: TracerPtr, cons_tracer: ConservativeTracer);
 
+/// Mutator threads stopped by [stop_the_world].
+pub(crate) struct StoppedWorld {
+    #[cfg(feature = "threaded")]
+    threads: parking_lot::MutexGuard<'static, Vec<*mut threading::TLSState>>,
+    #[cfg(feature = "threaded")]
+    old_state: i8,
+}
+
+/// Stop all mutator threads except the current one. Returns `None` if another thread started GC cycle first, in
+/// this case the current thread was suspended until that cycle finished.
+#[inline(never)]
+pub(crate) unsafe fn stop_the_world() -> Option<StoppedWorld> {
+    #[cfg(feature = "threaded")]
+    {
+        let ptls = immix_get_tls_state();
+        ptls.stack_end = get_sp!() as *mut _;
+        let old_state = ptls.gc_state;
+        ptls.atomic_gc_state()
+            .store(GC_STATE_WAITING, Ordering::Release);
+        if !safepoint::safepoint_start_gc() {
+            ptls.gc_state_set(old_state, GC_STATE_WAITING);
+            return None;
+        }
+        Some(StoppedWorld {
+            threads: safepoint::safepoint_wait_for_the_world(),
+            old_state,
+        })
+    }
+    #[cfg(not(feature = "threaded"))]
+    {
+        Some(StoppedWorld {})
+    }
+}
+
+/// Stop all mutator threads like [stop_the_world], if another thread started GC cycle first retry after it finished.
+pub(crate) unsafe fn stop_the_world_retry() -> StoppedWorld {
+    loop {
+        if let Some(world) = stop_the_world() {
+            return world;
+        }
+    }
+}
+
+/// Resume threads stopped by [stop_the_world].
+pub(crate) unsafe fn resume_the_world(world: StoppedWorld) {
+    #[cfg(feature = "threaded")]
+    {
+        safepoint::safepoint_end_gc(&*world.threads);
+        drop(world.threads);
+        immix_get_tls_state().gc_state_set(world.old_state, GC_STATE_WAITING);
+    }
+    #[cfg(not(feature = "threaded"))]
+    {
+        let _ = world;
+    }
+}
+
 impl Immix {
     #[allow(unused_variables)]
     #[inline(never)]
@@ -150,36 +255,22 @@ impl Immix {
         unsafe {
             let mut timer = util::timer::Timer::new(self.gc_stats != GcStats::None);
             crate::util::save_regs();
-            let old_state;
-            let threads;
-            let stop_threads;
             #[cfg(feature = "threaded")]
-            {
-                let start = time::Instant::now();
-                let ptls = immix_get_tls_state();
-                ptls.stack_end = get_sp!() as *mut _;
-                old_state = ptls.gc_state;
-                ptls.atomic_gc_state()
-                    .store(GC_STATE_WAITING, Ordering::Release);
-                if !safepoint::safepoint_start_gc() {
-                    ptls.gc_state_set(old_state, GC_STATE_WAITING);
-                    return;
-                }
-                threads = safepoint::safepoint_wait_for_the_world();
-                stop_threads = start.elapsed();
+            let start = time::Instant::now();
+            let world = match stop_the_world() {
+                Some(world) => world,
+                None => return,
             };
-            #[cfg(not(feature = "threaded"))]
-            {
-                stop_threads = ();
-                old_state = 0;
-                threads = ();
-            }
+            #[cfg(feature = "threaded")]
+            let stop_threads = start.elapsed();
             let collect_roots = time::Instant::now();
             let mut precise_roots = Vec::new();
+            let mut tagged_roots = Vec::new();
             let mut cons = Vec::new();
             for &(callback, data) in self.collect_roots_callback.iter() {
                 struct VisitRoots {
                     v: *mut Vec<*mut *mut RawGc>,
+                    tagged: *mut Vec<(*mut usize, usize)>,
                 }
 
                 impl Tracer for VisitRoots {
@@ -188,12 +279,26 @@ impl Immix {
                             (&mut *self.v).push(core::mem::transmute(reference));
                         }
                     }
+
+                    fn trace_tagged(&mut self, slot: &mut usize, tag_mask: usize) {
+                        // The world is stopped so the tag is stripped in place and restored after collection.
+                        let tag = *slot & tag_mask;
+                        if *slot & !tag_mask == 0 {
+                            return;
+                        }
+                        *slot &= !tag_mask;
+                        unsafe {
+                            (&mut *self.tagged).push((slot, tag));
+                            (&mut *self.v).push(slot as *mut usize as *mut *mut RawGc);
+                        }
+                    }
                 }
                 callback(
                     data,
                     TracerPtr {
                         tracer: core::mem::transmute(&mut VisitRoots {
                             v: &mut precise_roots,
+                            tagged: &mut tagged_roots,
                         } as &mut dyn Tracer),
                     },
                     ConservativeTracer {
@@ -206,7 +311,7 @@ impl Immix {
             let mut all_blocks = (*self.immix).get_all_blocks();
             #[cfg(feature = "threaded")]
             {
-                for thread in threads.iter() {
+                for thread in world.threads.iter() {
                     let thread = &mut **thread;
                     if let Some(block) = thread.current_block.take() {
                         all_blocks.push(block.0);
@@ -214,27 +319,61 @@ impl Immix {
                     if let Some(block) = thread.current_ovf_block.take() {
                         all_blocks.push(block.0);
                     }
-                    self.collect_roots(
+                    if let Some(block) = thread.current_atomic_block.take() {
+                        all_blocks.push(block.0);
+                    }
+                    if let Some(block) = thread.current_atomic_ovf_block.take() {
+                        all_blocks.push(block.0);
+                    }
+                    self.scan_stack(
                         thread.stack_bottom as *mut *mut u8,
                         thread.stack_end as *mut *mut u8,
                         &mut roots,
+                        &mut precise_roots,
+                    );
+                    self.collect_roots(
+                        thread.tls_begin as *mut *mut u8,
+                        thread.tls_end as *mut *mut u8,
+                        &mut roots,
                     );
                 }
             }
             for &(bottom, end) in cons.iter() {
                 self.collect_roots(bottom as *mut *mut u8, end as *mut *mut u8, &mut roots);
             }
+            // Roots may be added or removed by threads that are not stopped, scan a snapshot taken under the lock.
+            #[cfg(feature = "threaded")]
+            {
+                self.roots_lock.lock();
+            }
+            let static_roots = self.static_roots.clone();
+            #[cfg(feature = "threaded")]
+            {
+                self.roots_lock.unlock();
+            }
+            for (begin, end) in static_roots {
+                self.collect_roots(begin as *mut *mut u8, end as *mut *mut u8, &mut roots);
+            }
             #[cfg(not(feature = "threaded"))]
             {
-                self.collect_roots(
+                self.scan_stack(
                     self.stack_bottom as *mut *mut u8,
                     self.stack_end as *mut *mut u8,
                     &mut roots,
+                    &mut precise_roots,
                 );
             }
+            // Conservative roots are pinned so they are not moved by evacuation.
+            for &root in roots.iter() {
+                (&mut *root).pin();
+            }
             let collect_roots = collect_roots.elapsed();
+            let verify = verify::VERIFY.load(Ordering::Acquire);
+            if verify {
+                verify::verify_heap(self, &roots, &precise_roots, false, false);
+            }
             self.collector.extend_all_blocks(all_blocks);
-            let collection_type = self.collector.prepare_collection(
+            let mut collection_type = self.collector.prepare_collection(
                 evacuation,
                 true,
                 (*(*self.immix).block_allocator).available_blocks(),
@@ -242,17 +381,37 @@ impl Immix {
                 (*(*self.immix).block_allocator).total_blocks(),
                 emergency,
             );
+            let conservative = self.conservative_objects != 0;
+            if conservative {
+                // References found in conservatively scanned objects can't be updated so objects can't be moved.
+                collection_type = CollectionType::ImmixCollection;
+            }
             let mark = time::Instant::now();
 
-            let visited = self.collector.collect(
+            let result = self.collector.collect(
                 &collection_type,
                 &roots,
                 &precise_roots,
                 &mut *self.immix,
+                &mut self.mos,
                 &mut self.los,
                 !self.current_live_mark,
+                conservative,
             );
             let mark = mark.elapsed();
+            if verify {
+                verify::verify_heap(self, &roots, &precise_roots, true, !self.current_live_mark);
+            }
+            let visited = result.visited;
+            self.conservative_objects = result.conservative_objects;
+            for object in result.pinned.iter() {
+                (&mut **object).unpin();
+            }
+            for &(slot, tag) in tagged_roots.iter() {
+                *slot |= tag;
+            }
+            (*self.immix).update_blacklist(&self.false_roots);
+            self.false_roots.clear();
             for root in roots.iter() {
                 {
                     (&mut **root).unpin()
@@ -271,6 +430,12 @@ impl Immix {
                 }
                 cursor.move_next();
             }
+            let relocations = (*self.immix).take_relocations();
+            if !relocations.is_empty() {
+                for &(callback, data) in self.relocation_callbacks.iter() {
+                    callback(data, relocations.as_ptr(), relocations.len());
+                }
+            }
             self.current_live_mark = !self.current_live_mark;
             (*self.immix).set_current_live_mark(self.current_live_mark);
             self.los.current_live_mark = self.current_live_mark;
@@ -280,6 +445,13 @@ impl Immix {
             if visited >= self.threshold {
                 self.threshold = (visited as f64 * 1.75) as usize;
             }
+            let large_live = self.los.live_bytes();
+            if large_live >= self.large_threshold {
+                self.large_threshold = (large_live as f64 * 1.75) as usize;
+            }
+            self.stats.medium_freed += result.medium_freed;
+            self.stats.large_freed += result.large_freed;
+            self.medium_exhausted = false;
             if self.gc_stats != GcStats::None {
                 let duration = timer.stop();
                 self.stats.add(duration);
@@ -322,117 +494,244 @@ impl Immix {
                         mark.whole_nanoseconds() as u64,
                     );
                     #[cfg(unix)]
+                    printf(
+                        b"Medium objects: freed %lu bytes\n\0".as_ptr().cast(),
+                        result.medium_freed as u64,
+                    );
+                    #[cfg(unix)]
+                    printf(
+                        b"Large objects: freed %lu bytes, %lu bytes live\n\0"
+                            .as_ptr()
+                            .cast(),
+                        result.large_freed as u64,
+                        large_live as u64,
+                    );
+                    #[cfg(unix)]
+                    printf(
+                        b"Blacklisted %lu bytes\n\0".as_ptr().cast(),
+                        (*self.immix).blacklisted_bytes() as u64,
+                    );
+                    #[cfg(unix)]
                     printf(
                         "Whole GC cycle took %.6f ms\n\0".as_ptr().cast(),
                         duration as libc::c_double,
                     );
                 }
             }
-            #[cfg(feature = "threaded")]
-            {
-                safepoint::safepoint_end_gc(&*threads);
-                drop(threads);
-                immix_get_tls_state().gc_state_set(old_state, GC_STATE_WAITING);
-            }
+            resume_the_world(world);
         }
     }
 
+    /// Scan thread stack from `from` to `to`. Frames described by stack maps are scanned precisely and
+    /// the rest of the stack is scanned conservatively.
+    unsafe fn scan_stack(
+        &mut self,
+        from: *mut *mut u8,
+        to: *mut *mut u8,
+        into: &mut Vec<*mut RawGc>,
+        precise_roots: &mut Vec<*mut *mut RawGc>,
+    ) {
+        if self.stack_maps.is_empty() {
+            self.collect_roots(from, to, into);
+            return;
+        }
+        let (from, to) = if from < to { (from, to) } else { (to, from) };
+        let mut frames = Vec::new();
+        self.stack_maps
+            .scan_frames(from.cast(), to.cast(), precise_roots, &mut frames);
+        let mut scan = from as usize;
+        for &(begin, end) in frames.iter() {
+            self.collect_roots(scan as *mut *mut u8, begin as *mut *mut u8, into);
+            scan = end;
+        }
+        self.collect_roots(scan as *mut *mut u8, to, into);
+    }
+
     unsafe fn collect_roots(
         &mut self,
         from: *mut *mut u8,
         to: *mut *mut u8,
         into: &mut Vec<*mut RawGc>,
     ) {
-        let mut scan = align_usize(from as usize, 16) as *mut *mut u8;
-        let mut end = align_usize(to as usize, 16) as *mut *mut u8;
+        let mut scan = from;
+        let mut end = to;
         if scan.is_null() || end.is_null() {
             return;
         }
         if scan > end {
             core::mem::swap(&mut scan, &mut end);
         }
+        // Roots might be registered for any word in memory so do not skip words that are not 16 byte aligned.
+        let word = core::mem::size_of::<usize>();
+        let mut scan = (scan as usize & !(word - 1)) as *mut *mut u8;
+        let end = align_usize(end as usize, word) as *mut *mut u8;
 
         debug!("Collect roots from {:p} to {:p}", scan, end);
+        let tag_mask = CONSERVATIVE_TAG_MASK.load(Ordering::Relaxed);
         while scan < end {
-            let ptr = *scan;
+            let ptr = (*scan as usize & !tag_mask) as *mut u8;
             if ptr.is_null() {
                 scan = scan.offset(1);
                 continue;
             }
 
-            if PreciseAllocation::is_precise(ptr.cast())
-                && self.los.contains(Address::from_ptr(ptr))
-            {
-                (&mut *ptr.cast::<RawGc>()).pin();
-                into.push(ptr.cast::<RawGc>());
-                debug!("Found root from large object space {:p} at {:p}", ptr, scan);
-                scan = scan.offset(1);
-                continue;
-            }
-            pub fn align_down(addr: usize, align: usize) -> usize {
-                /*if !align.is_power_of_two() {
-                    panic!("align should be power of two");
-                }*/
-                addr & !(align - 1)
-            }
-            //let ptr = align_down(ptr as usize, 16) as *mut u8;
-            if let Some(ptr) = (*self.immix).filter(Address::from_ptr(ptr)) {
-                let ptr = ptr.to_mut_ptr::<u8>();
-
-                (&mut *ptr.cast::<RawGc>()).pin();
-
-                into.push(ptr.cast());
-                debug!("Found root {:p} at {:p}", ptr, scan);
+            if let Some(object) = collector::find_object(&*self.immix, &self.mos, &self.los, ptr) {
+                into.push(object);
+                debug!("Found root {:p} at {:p}", object, scan);
+            } else if (*self.immix).filter_fast(Address::from_ptr(ptr)) {
+                self.false_roots.push(Address::from_ptr(ptr));
             }
-            let ptr = ptr.sub(8);
-            if let Some(ptr) = (*self.immix).filter(Address::from_ptr(ptr)) {
-                let ptr = ptr.to_mut_ptr::<u8>();
+            scan = scan.offset(1);
+        }
+    }
+    /// Register writable data segments of all loaded objects as static roots.
+    fn register_static_roots(&mut self) {
+        let mut segments = Vec::new();
+        static_roots::data_segments(&mut segments);
+        for (begin, end) in segments {
+            debug!(
+                "Register data segment {:p}->{:p}",
+                begin as *const u8, end as *const u8
+            );
+            self.add_roots(begin, end);
+        }
+    }
 
-                (&mut *ptr.cast::<RawGc>()).pin();
+    fn add_roots(&mut self, begin: usize, end: usize) {
+        if begin >= end {
+            return;
+        }
+        #[cfg(feature = "threaded")]
+        {
+            self.roots_lock.lock();
+        }
+        self.static_roots.push((begin, end));
+        #[cfg(feature = "threaded")]
+        unsafe {
+            self.roots_lock.unlock();
+        }
+    }
 
-                into.push(ptr.cast());
-                debug!("Found root {:p} at {:p}", ptr, scan);
-            }
-            scan = scan.offset(1);
+    fn remove_roots(&mut self, begin: usize, end: usize) {
+        #[cfg(feature = "threaded")]
+        {
+            self.roots_lock.lock();
+        }
+        self.static_roots
+            .retain(|&(b, e)| !(begin <= b && e <= end));
+        #[cfg(feature = "threaded")]
+        unsafe {
+            self.roots_lock.unlock();
         }
     }
+
     #[inline]
     #[allow(unused_unsafe)]
-    fn allocate(&mut self, size: usize, rtti: usize) -> usize {
+    /// Allocate object of `size` bytes including header. If `align` is above 8 the value of object is placed at
+    /// the first address aligned to `align` after the header and one word that holds distance from the header to
+    /// the value (see `GcPointee::header_of`).
+    fn allocate(&mut self, size: usize, align: usize, rtti: usize, no_scan: bool) -> usize {
         unsafe {
             self.stack_end = get_sp!() as *mut u8;
-            if self.allocated >= self.threshold {
+            if self.allocated >= self.threshold || self.los.live_bytes() >= self.large_threshold {
                 //panic!();
                 self.collect_internal(false, true);
             }
-            let size = align_usize(size, 16);
+            let header_size = core::mem::size_of::<RawGc>();
+            // In Immix and medium object space the header is aligned to `align`, in large object space the value is.
+            let value_offset = if align <= 8 {
+                header_size
+            } else {
+                align_usize(header_size + 8, align)
+            };
+            let large = align_usize(size, 16) >= LARGE_OBJECT;
+            let medium_size = align_usize(size + value_offset - header_size, 16);
+            let mut medium = large && MediumObjectSpace::fits(medium_size, align);
+            let mut large = large && !medium;
+            let mut medium_cell = Address::null();
+            if medium {
+                medium_cell = self.mos.alloc(medium_size, no_scan);
+                if medium_cell.is_null() && !self.medium_exhausted {
+                    self.collect_internal(true, true);
+                    medium_cell = self.mos.alloc(medium_size, no_scan);
+                    self.medium_exhausted = medium_cell.is_null();
+                }
+                // medium object space is exhausted, fall back to large object space
+                medium = !medium_cell.is_null();
+                large = !medium;
+            }
+            let value_offset = if large && align > 8 {
+                PreciseAllocation::ALIGNED_VALUE_OFFSET
+            } else {
+                value_offset
+            };
+            let padding = value_offset - header_size;
+            let size = align_usize(size + padding, 16);
 
-            let ptr = if size >= LARGE_OBJECT {
-                self.los.alloc(size, rtti)
+            let ptr = if large {
+                let mut addr = self.los.alloc(size, rtti, no_scan, align);
+                if addr.is_null() {
+                    self.collect_internal(true, true);
+                    addr = self.los.alloc(size, rtti, no_scan, align);
+                    if addr.is_null() {
+                        return 0;
+                    }
+                }
+                addr
+            } else if medium {
+                core::ptr::write_bytes(
+                    medium_cell.offset(header_size).to_mut_ptr::<u8>(),
+                    0,
+                    padding,
+                );
+                medium_cell
             } else {
-                let mut addr = (*self.immix).allocate(size, 0);
+                let mut addr = (*self.immix).allocate(size, align, no_scan);
                 if addr.is_null() {
                     self.collect_internal(true, true);
-                    addr = (*self.immix).allocate(size, 0);
+                    addr = (*self.immix).allocate(size, align, no_scan);
                     if addr.is_null() {
                         return 0;
                     }
                 }
+                core::ptr::write_bytes(addr.cast::<u8>().add(header_size), 0, padding);
                 Address::from_ptr(addr)
             };
-            #[cfg(feature = "threaded")]
-            {
-                as_atomic!(&self.allocated;AtomicUsize)
-                    .fetch_add(size, core::sync::atomic::Ordering::AcqRel);
+            if padding != 0 {
+                ptr.offset(value_offset - 8)
+                    .to_mut_ptr::<usize>()
+                    .write(value_offset);
             }
-            #[cfg(not(feature = "threaded"))]
-            {
-                self.allocated += size;
+            // large object space accounts its own bytes
+            if !large {
+                #[cfg(feature = "threaded")]
+                {
+                    as_atomic!(&self.allocated;AtomicUsize)
+                        .fetch_add(size, core::sync::atomic::Ordering::AcqRel);
+                }
+                #[cfg(not(feature = "threaded"))]
+                {
+                    self.allocated += size;
+                }
             }
             let raw = &mut *ptr.to_mut_ptr::<RawGc>();
             *raw = RawGc::new(rtti);
             raw.mark(self.current_live_mark);
-            if (*raw).rtti().needs_finalization && size < LARGE_OBJECT {
+            if self.profiler.enabled.load(Ordering::Relaxed) {
+                self.profiler.allocated(size, rtti);
+            }
+            if raw.rtti().conservative {
+                #[cfg(feature = "threaded")]
+                {
+                    as_atomic!(&self.conservative_objects;AtomicUsize)
+                        .fetch_add(1, core::sync::atomic::Ordering::AcqRel);
+                }
+                #[cfg(not(feature = "threaded"))]
+                {
+                    self.conservative_objects += 1;
+                }
+            }
+            if (*raw).rtti().needs_finalization && !large && !medium {
                 #[cfg(feature = "threaded")]
                 {
                     self.fin_lock.lock();
@@ -447,6 +746,25 @@ impl Immix {
         }
     }
 
+    /// Push `object` to list of objects that are finalized once they are dead. Objects in medium and large object
+    /// space are always finalized when sweeped.
+    pub(crate) fn add_finalizable(&mut self, object: *mut RawGc) {
+        unsafe {
+            if (*object).is_precise_allocation() || self.mos.contains(Address::from_ptr(object)) {
+                return;
+            }
+        }
+        #[cfg(feature = "threaded")]
+        {
+            self.fin_lock.lock();
+        }
+        self.to_finalize.push_back(object);
+        #[cfg(feature = "threaded")]
+        unsafe {
+            self.fin_lock.unlock();
+        }
+    }
+
     fn new(size: usize, threshold: usize) -> Self {
         Self {
             timer: util::timer::Timer::new(false),
@@ -459,23 +777,67 @@ impl Immix {
             },
             allocated: 0,
             threshold,
+            large_threshold: threshold,
+            medium_exhausted: false,
             immix: ImmixSpace::new(align_usize(size + BLOCK_SIZE, *PAGESIZE)),
-            los: LargeObjectSpace::new(),
+            los: LargeObjectSpace::new(LARGE_OBJECT_SPACE_SIZE),
+            mos: MediumObjectSpace::new(size),
             stack_end: 0 as *mut _,
             stack_bottom: 0 as *mut _,
             current_live_mark: false,
             collect_roots_callback: Vec::new(),
-
+            relocation_callbacks: Vec::new(),
+            stack_maps: stack_map::StackMaps::new(),
+            static_roots: Vec::new(),
+            false_roots: Vec::new(),
+            conservative_objects: 0,
+            #[cfg(feature = "threaded")]
+            roots_lock: Mutex::new(),
             to_finalize: LinkedList::new(),
             #[cfg(feature = "threaded")]
             fin_lock: Mutex::new(),
             collector: Collector::new(),
+            profiler: profiler::Profiler::new(),
         }
     }
 }
 #[cfg_attr(not(feature = "threaded"), thread_local)]
 static mut SPACE: *mut Immix = 0 as *mut _;
 
+/// Bits that are stripped from each word before it is checked as a conservative root.
+pub(crate) static CONSERVATIVE_TAG_MASK: AtomicUsize = AtomicUsize::new(0);
+
+/// Set bits that are stripped from words found by conservative root scanning before they are checked for
+/// pointers into the heap. Use this when VM stores tagged pointers on stack (e.g low-bit tags).
+#[no_mangle]
+pub extern "C" fn immix_set_conservative_tag_mask(tag_mask: usize) {
+    CONSERVATIVE_TAG_MASK.store(tag_mask, Ordering::Relaxed);
+}
+
+/// Return number of bytes in live large objects. Objects that died since the last GC cycle are counted too.
+#[no_mangle]
+pub extern "C" fn immix_large_object_live_bytes() -> usize {
+    unsafe { (*SPACE).los.live_bytes() }
+}
+
+/// Return total number of bytes freed in medium object space.
+#[no_mangle]
+pub extern "C" fn immix_medium_object_freed_bytes() -> usize {
+    unsafe { (*SPACE).stats.medium_freed }
+}
+
+/// Return total number of bytes freed in large object space.
+#[no_mangle]
+pub extern "C" fn immix_large_object_freed_bytes() -> usize {
+    unsafe { (*SPACE).stats.large_freed }
+}
+
+/// Return number of bytes in heap lines that are blacklisted because false conservative pointers point into them.
+#[no_mangle]
+pub extern "C" fn immix_blacklisted_bytes() -> usize {
+    unsafe { (*(*SPACE).immix).blacklisted_bytes() }
+}
+
 /// Register callback that will be invoked when GC starts.
 ///
 ///
@@ -537,6 +899,13 @@ pub extern "C" fn immix_init(
             mem.write(space);
             SPACE = mem;
             (*SPACE).timer = util::timer::Timer::new(true);
+            if static_roots::SCAN_STATIC_ROOTS.load(Ordering::Acquire) {
+                (*SPACE).register_static_roots();
+                #[cfg(not(feature = "threaded"))]
+                {
+                    threading::register_thread_local_roots();
+                }
+            }
         }
     }
 }
@@ -562,7 +931,8 @@ pub struct GCObject {
 }
 
 /// Allocate memory of `size + sizeof(GCObject)` bytes in Immix heap and set object RTTI to `rtti`. If `size` >= 8KB then
-/// object is allocated inside large object space.
+/// object is allocated inside medium object space, objects above 256KB and objects that do not fit into exhausted
+/// medium object space are allocated inside large object space.
 ///
 ///
 /// ## Return value
@@ -571,18 +941,127 @@ pub struct GCObject {
 #[no_mangle]
 #[inline]
 pub extern "C" fn immix_alloc(size: usize, rtti: *const GCRTTI) -> *mut GCObject {
-    unsafe { (*SPACE).allocate(size, rtti as _) as *mut GCObject }
+    unsafe { (*SPACE).allocate(size, 0, rtti as _, false) as *mut GCObject }
+}
+
+/// Allocate object like [immix_alloc] but place its value at address aligned to `align`. If `align` is above 8 the
+/// value is placed at the first aligned address after the header and one word that holds distance from the header
+/// to the value, so `heap_size` of `rtti` must return this distance plus size of value. `rtti->alignment` must be
+/// equal to `align` so evacuation keeps the alignment.
+///
+/// ## Return value
+/// Returns pointer to allocated memory or null if allocation failed after emergency GC cycle or `align` is not a
+/// power of two or is larger than `MAX_ALIGNMENT`.
+#[no_mangle]
+pub extern "C" fn immix_alloc_aligned(
+    size: usize,
+    align: usize,
+    rtti: *const GCRTTI,
+) -> *mut GCObject {
+    if !align.is_power_of_two() || align > constants::MAX_ALIGNMENT {
+        return core::ptr::null_mut();
+    }
+    unsafe { (*SPACE).allocate(size, align, rtti as _, false) as *mut GCObject }
+}
+
+/// Allocate memory for object that does not contain any references into GC heap. Such objects are
+/// never scanned: GC marks them without invoking `visit_references` of `rtti`.
+///
+/// ## Return value
+/// Returns pointer to allocated memory or null if allocation failed after emergency GC cycle.
+#[no_mangle]
+pub extern "C" fn immix_alloc_atomic(size: usize, rtti: *const GCRTTI) -> *mut GCObject {
+    unsafe { (*SPACE).allocate(size, 0, rtti as _, true) as *mut GCObject }
 }
 
+/// Allocate `value` in GC heap. Types with `#[repr(align(N))]` are supported up to `MAX_ALIGNMENT`.
 pub fn immix_alloc_safe<T: HeapObject>(value: T) -> Gc<T> {
     unsafe {
-        let ptr = immix_alloc(value.heap_size(), object_ty_of_type::<T>() as *mut _);
-        let ptr = ptr as *mut RawGc;
-        (*ptr).data().cast::<T>().write(value);
+        assert!(core::mem::align_of::<T>() <= constants::MAX_ALIGNMENT);
+        let size = value.heap_size() + core::mem::size_of::<RawGc>();
+        let ptr = (*SPACE).allocate(
+            size,
+            core::mem::align_of::<T>(),
+            object_ty_of_type::<T>(),
+            T::NO_SCAN,
+        ) as *mut RawGc;
+        T::from_data((*ptr).data()).write(value);
+        Gc {
+            marker: Default::default(),
+            ptr: NonNull::new_unchecked(ptr),
+        }
+    }
+}
+
+/// Allocate memory for dynamically sized object and write its metadata. Returns pointer to memory for the value.
+///
+/// # Panics
+/// Panics if alignment of `value` is above word size, the value is only aligned to 8 bytes.
+unsafe fn alloc_unsized<T: HeapObject + GcPointee + ?Sized>(
+    value: *const T,
+    value_size: usize,
+) -> (Gc<T>, *mut u8) {
+    assert!(
+        core::mem::align_of_val(&*value) <= core::mem::size_of::<usize>(),
+        "values with alignment above word size are not supported"
+    );
+    let size = core::mem::size_of::<RawGc>() + T::METADATA_SIZE + value_size;
+    let rtti = object_ty_of(value) as *const GCRTTI;
+    let ptr = if T::NO_SCAN {
+        immix_alloc_atomic(size, rtti)
+    } else {
+        immix_alloc(size, rtti)
+    } as *mut RawGc;
+    assert!(!ptr.is_null(), "out of memory");
+    let data = (*ptr).data();
+    data.cast::<usize>().write(T::metadata(value));
+    (
         Gc {
             marker: Default::default(),
             ptr: NonNull::new_unchecked(ptr),
+        },
+        data.add(T::METADATA_SIZE),
+    )
+}
+
+/// Allocate `value` as dynamically sized `T`, i.e `[T; N]` as `[T]` or a type implementing `Trait` as `dyn Trait`
+/// (see [impl_heap_object_for_dyn]).
+///
+/// # Panics
+/// Panics if alignment of `U` is above word size.
+pub fn immix_alloc_unsize<T, U>(value: U) -> Gc<T>
+where
+    T: HeapObject + GcPointee + ?Sized,
+    U: core::marker::Unsize<T>,
+{
+    unsafe {
+        let (object, data) =
+            alloc_unsized::<T>(&value as *const U as *const T, core::mem::size_of::<U>());
+        data.cast::<U>().write(value);
+        object
+    }
+}
+
+/// Allocate slice with clones of `values`. `Clone` of `T` must not allocate in GC heap.
+///
+/// # Panics
+/// Panics if alignment of `T` is above word size.
+pub fn immix_alloc_slice<T: Trace + Clone>(values: &[T]) -> Gc<[T]> {
+    unsafe {
+        let (object, data) = alloc_unsized::<[T]>(values, core::mem::size_of_val(values));
+        for (ix, value) in values.iter().enumerate() {
+            data.cast::<T>().add(ix).write(value.clone());
         }
+        object
+    }
+}
+
+/// Allocate copy of `s`.
+pub fn immix_alloc_str(s: &str) -> Gc<str> {
+    unsafe {
+        let (object, data) = alloc_unsized::<str>(s, s.len());
+        core::ptr::copy_nonoverlapping(s.as_ptr(), data, s.len());
+        object
     }
 }
 
@@ -632,6 +1111,10 @@ struct CollectionStats {
     collections: usize,
     total_pause: f32,
     pauses: Vec<f32>,
+    /// Total number of bytes freed in medium object space.
+    medium_freed: usize,
+    /// Total number of bytes freed in large object space.
+    large_freed: usize,
 }
 
 impl CollectionStats {
@@ -640,6 +1123,8 @@ impl CollectionStats {
             collections: 0,
             total_pause: 0f32,
             pauses: Vec::new(),
+            medium_freed: 0,
+            large_freed: 0,
         }
     }
 
@@ -0,0 +1,287 @@
+use crate::block_allocator::Mmap;
+use crate::constants::*;
+use crate::object::*;
+use crate::util::*;
+use alloc::vec::Vec;
+
+/// Size of one chunk of `MEDIUM_CHUNK_BLOCKS` contiguous blocks.
+pub const CHUNK_SIZE: usize = MEDIUM_CHUNK_BLOCKS * BLOCK_SIZE;
+
+/// Cell sizes of medium objects, four classes per power of two from `LARGE_OBJECT` to `MEDIUM_OBJECT_LIMIT`.
+/// The smallest class must fit at most 64 cells into a chunk so bitmaps of a chunk fit into one word.
+pub const SIZE_CLASSES: [usize; 21] = [
+    8 * 1024,
+    10 * 1024,
+    12 * 1024,
+    14 * 1024,
+    16 * 1024,
+    20 * 1024,
+    24 * 1024,
+    28 * 1024,
+    32 * 1024,
+    40 * 1024,
+    48 * 1024,
+    56 * 1024,
+    64 * 1024,
+    80 * 1024,
+    96 * 1024,
+    112 * 1024,
+    128 * 1024,
+    160 * 1024,
+    192 * 1024,
+    224 * 1024,
+    256 * 1024,
+];
+
+/// Size class of chunk that holds no objects.
+const FREE_CHUNK: u8 = u8::MAX;
+
+/// Chunk of medium object space. All cells of a chunk have the same size class, bit `i` of each bitmap is for
+/// cell `i`.
+#[derive(Clone, Copy)]
+struct Chunk {
+    /// Size class of cells or `FREE_CHUNK`.
+    class: u8,
+    /// Cells that hold objects.
+    allocated: u64,
+    /// Cells that hold objects marked in current GC cycle.
+    marked: u64,
+    /// Cells that hold objects without references.
+    no_scan: u64,
+}
+
+impl Chunk {
+    const FREE: Self = Self {
+        class: FREE_CHUNK,
+        allocated: 0,
+        marked: 0,
+        no_scan: 0,
+    };
+
+    fn cell_size(&self) -> usize {
+        SIZE_CLASSES[self.class as usize]
+    }
+
+    fn cells(&self) -> usize {
+        CHUNK_SIZE / self.cell_size()
+    }
+}
+
+/// Size-segregated space for objects of `LARGE_OBJECT` bytes up to `MEDIUM_OBJECT_LIMIT`.
+///
+/// Memory is reserved up front and handed out in chunks of `MEDIUM_CHUNK_BLOCKS` contiguous blocks, each chunk is
+/// split into cells of one size class. Free cells of each class are kept in a free list that is rebuilt from chunk
+/// bitmaps on every sweep, chunks without live objects are decommitted and reused for any class.
+/// Medium objects are never moved by the garbage collector.
+pub struct MediumObjectSpace {
+    mmap: Mmap,
+    /// Start of the region aligned to `BLOCK_SIZE`.
+    start: usize,
+    chunks: Vec<Chunk>,
+    /// Index of the first chunk that was never allocated.
+    cursor: usize,
+    /// Free chunks below `cursor`.
+    free_chunks: Vec<usize>,
+    /// Addresses of free cells of each size class.
+    free_cells: Vec<Vec<usize>>,
+}
+
+impl MediumObjectSpace {
+    /// Maximal alignment of objects in this space. Objects with larger alignment are allocated in large object space.
+    pub const MAX_ALIGNMENT: usize = 16;
+
+    /// Reserve region of `size` bytes for medium objects.
+    pub fn new(size: usize) -> Self {
+        let size = align_usize(size, CHUNK_SIZE);
+        let mmap = Mmap::new(size + BLOCK_SIZE);
+        let start = mmap.aligned() as usize;
+        debug!(
+            "New medium object space from 0x{:x} to 0x{:x}",
+            start,
+            start + size
+        );
+        Self {
+            mmap,
+            start,
+            chunks: alloc::vec![Chunk::FREE; size / CHUNK_SIZE],
+            cursor: 0,
+            free_chunks: Vec::new(),
+            free_cells: alloc::vec![Vec::new(); SIZE_CLASSES.len()],
+        }
+    }
+
+    /// Return true if object of `size` bytes with alignment `align` is allocated in this space.
+    pub fn fits(size: usize, align: usize) -> bool {
+        (LARGE_OBJECT..=MEDIUM_OBJECT_LIMIT).contains(&size) && align <= Self::MAX_ALIGNMENT
+    }
+
+    /// Return true if `p` is inside of chunks that were allocated at least once. This does not mean that `p`
+    /// points to an object.
+    #[inline]
+    pub fn in_space(&self, p: Address) -> bool {
+        p.to_usize() >= self.start && p.to_usize() < self.start + self.cursor * CHUNK_SIZE
+    }
+
+    /// Return chunk index and cell index of cell that contains `p`. Cell might be free.
+    fn cell_of(&self, p: Address) -> Option<(usize, usize)> {
+        if !self.in_space(p) {
+            return None;
+        }
+        let offset = p.to_usize() - self.start;
+        let chunk = &self.chunks[offset / CHUNK_SIZE];
+        if chunk.class == FREE_CHUNK {
+            return None;
+        }
+        let cell = offset % CHUNK_SIZE / chunk.cell_size();
+        if cell >= chunk.cells() {
+            return None;
+        }
+        Some((offset / CHUNK_SIZE, cell))
+    }
+
+    fn cell_address(&self, chunk: usize, cell: usize) -> usize {
+        self.start + chunk * CHUNK_SIZE + cell * self.chunks[chunk].cell_size()
+    }
+
+    /// Find medium object that contains `p`.
+    pub fn find_interior(&self, p: Address) -> Option<*mut RawGc> {
+        let (chunk, cell) = self.cell_of(p)?;
+        if self.chunks[chunk].allocated & (1 << cell) == 0 {
+            return None;
+        }
+        Some(self.cell_address(chunk, cell) as *mut RawGc)
+    }
+
+    /// Return true if `p` points to a medium object.
+    pub fn contains(&self, p: Address) -> bool {
+        self.find_interior(p) == Some(p.to_mut_ptr())
+    }
+
+    /// Set mark bit of `object`. `object` must be allocated in this space.
+    pub fn mark(&mut self, object: Address) {
+        let (chunk, cell) = self.cell_of(object).unwrap();
+        self.chunks[chunk].marked |= 1 << cell;
+    }
+
+    /// Return true if `object` does not contain references. `object` must be allocated in this space.
+    pub fn is_no_scan(&self, object: Address) -> bool {
+        let (chunk, cell) = self.cell_of(object).unwrap();
+        self.chunks[chunk].no_scan & (1 << cell) != 0
+    }
+
+    /// Return number of bytes in committed chunks.
+    pub fn committed_bytes(&self) -> usize {
+        self.chunks[..self.cursor]
+            .iter()
+            .filter(|chunk| chunk.class != FREE_CHUNK)
+            .count()
+            * CHUNK_SIZE
+    }
+
+    /// Invoke `visitor` on every allocated object.
+    pub fn visit_objects(&self, mut visitor: impl FnMut(*mut RawGc)) {
+        for (ix, chunk) in self.chunks[..self.cursor].iter().enumerate() {
+            let mut allocated = chunk.allocated;
+            while allocated != 0 {
+                let cell = allocated.trailing_zeros() as usize;
+                allocated &= allocated - 1;
+                visitor(self.cell_address(ix, cell) as *mut RawGc);
+            }
+        }
+    }
+
+    /// Take a free chunk for cells of `class` and put its cells except the first one to the free list.
+    /// Returns address of the first cell.
+    fn refill(&mut self, class: usize) -> Option<usize> {
+        let ix = match self.free_chunks.pop() {
+            Some(ix) => ix,
+            None if self.cursor < self.chunks.len() => {
+                self.cursor += 1;
+                self.cursor - 1
+            }
+            None => return None,
+        };
+        let begin = self.start + ix * CHUNK_SIZE;
+        self.mmap.commit(begin as *mut u8, CHUNK_SIZE);
+        self.chunks[ix].class = class as u8;
+        let cell_size = SIZE_CLASSES[class];
+        let cells = CHUNK_SIZE / cell_size;
+        // cells are taken from the end of list, lower addresses go first
+        self.free_cells[class].extend((1..cells).rev().map(|cell| begin + cell * cell_size));
+        Some(begin)
+    }
+
+    /// Allocate cell for object of `size` bytes. Returns null address if the region is exhausted.
+    pub fn alloc(&mut self, size: usize, no_scan: bool) -> Address {
+        let class = match SIZE_CLASSES.iter().position(|&cell_size| cell_size >= size) {
+            Some(class) => class,
+            None => return Address::null(),
+        };
+        let cell = match self.free_cells[class].pop() {
+            Some(cell) => cell,
+            None => match self.refill(class) {
+                Some(cell) => cell,
+                None => return Address::null(),
+            },
+        };
+        let (chunk, ix) = self.cell_of(Address::from(cell)).unwrap();
+        let chunk = &mut self.chunks[chunk];
+        chunk.allocated |= 1 << ix;
+        if no_scan {
+            chunk.no_scan |= 1 << ix;
+        } else {
+            chunk.no_scan &= !(1 << ix);
+        }
+        Address::from(cell)
+    }
+
+    /// Free objects that were not marked since the last sweep and return number of freed bytes. Finalizers of
+    /// dead objects are invoked before their memory is reused.
+    pub fn sweep(&mut self) -> usize {
+        let mut freed = 0;
+        for list in self.free_cells.iter_mut() {
+            list.clear();
+        }
+        for ix in 0..self.cursor {
+            let chunk = self.chunks[ix];
+            if chunk.class == FREE_CHUNK {
+                continue;
+            }
+            let begin = self.start + ix * CHUNK_SIZE;
+            let cell_size = chunk.cell_size();
+            let mut dead = chunk.allocated & !chunk.marked;
+            while dead != 0 {
+                let cell = dead.trailing_zeros() as usize;
+                dead &= dead - 1;
+                let object = (begin + cell * cell_size) as *mut RawGc;
+                unsafe {
+                    let rtti = (*object).rtti();
+                    if rtti.needs_finalization {
+                        if let Some(fin) = rtti.finalizer {
+                            fin(object as *mut u8);
+                        }
+                    }
+                }
+                freed += cell_size;
+            }
+            let chunk = &mut self.chunks[ix];
+            chunk.allocated = chunk.marked;
+            chunk.no_scan &= chunk.marked;
+            chunk.marked = 0;
+            if chunk.allocated == 0 {
+                *chunk = Chunk::FREE;
+                self.mmap.dontneed(begin as *mut u8, CHUNK_SIZE);
+                self.free_chunks.push(ix);
+                continue;
+            }
+            let allocated = chunk.allocated;
+            self.free_cells[chunk.class as usize].extend(
+                (0..chunk.cells())
+                    .rev()
+                    .filter(|&cell| allocated & (1 << cell) == 0)
+                    .map(|cell| begin + cell * cell_size),
+            );
+        }
+        freed
+    }
+}
@@ -1,10 +1,21 @@
 use crate::util::*;
-use core::ops::{Deref, DerefMut};
+use core::ops::Deref;
 use core::ptr::*;
+#[cfg(feature = "header-word")]
+use core::sync::atomic::{AtomicUsize, Ordering};
 use core::{i16, marker::PhantomData};
+/// Header bit set once identity hash of object is computed from its address.
+const HASHED_BIT: usize = 62;
+/// Header bit set when object was moved after it was hashed. Identity hash is stored in the slot after the object.
+const HASHED_AND_MOVED_BIT: usize = 63;
+
 #[repr(C)]
 pub struct RawGc {
     pub vtable: TaggedPointer<usize>,
+    /// Header word reserved for users of the collector (identity hash, lock word, flags). It is zero for new
+    /// objects and is never touched by the collector, so evacuation preserves it.
+    #[cfg(feature = "header-word")]
+    pub word: AtomicUsize,
 }
 
 /// Visits garbage collected objects
@@ -13,72 +24,238 @@ pub struct RawGc {
 pub trait Tracer {
     /// Traces a reference to a specified value.
     fn trace(&mut self, reference: &mut NonNull<RawGc>);
+    /// Traces a tagged reference stored in `slot`.
+    ///
+    /// Bits of `tag_mask` are stripped before tracing and reapplied to the (possibly forwarded) pointer
+    /// afterwards. Nothing is traced if the untagged value is null.
+    ///
+    /// Tags are not interpreted, so `slot` must hold a tagged pointer to an object or a tagged null. Callers must
+    /// check tags of values that are not pointers (i.e small integers) and skip them.
+    fn trace_tagged(&mut self, slot: &mut usize, tag_mask: usize) {
+        let tag = *slot & tag_mask;
+        if let Some(mut reference) = NonNull::new((*slot & !tag_mask) as *mut RawGc) {
+            self.trace(&mut reference);
+            *slot = reference.as_ptr() as usize | tag;
+        }
+    }
 }
 
 #[macro_export]
 macro_rules! make_rtti_for {
     ($t: ty) => {
-        GCRTTI {
-            visit_references: {
-                extern "C" fn visit(data: *mut u8, trace: TracerPtr) {
-                    unsafe {
-                        (*data.add(8).cast::<$t>()).visit_references(&mut *core::mem::transmute::<
-                            [usize; 2],
-                            *mut dyn Tracer,
-                        >(
-                            trace.tracer
-                        ));
-                    }
-                }
-                visit
-            },
-            finalizer: Some({
-                extern "C" fn fin(data: *mut u8) {
-                    unsafe {
-                        core::ptr::drop_in_place(data.add(8).cast::<$t>());
-                    }
-                }
-                fin
-            }),
+        $crate::object::GCRTTI {
+            visit_references: $crate::object::rtti_visit_references::<$t>,
+            finalizer: Some($crate::object::rtti_finalize::<$t>),
             needs_finalization: false,
-            heap_size: {
-                extern "C" fn size(data: *mut u8) -> usize {
-                    unsafe { (*data.add(8).cast::<$t>()).heap_size() + 8 }
-                }
-                size
-            },
+            conservative: false,
+            heap_size: $crate::object::rtti_heap_size::<$t>,
+            alignment: core::mem::align_of::<$t>(),
+            notify_relocation: false,
         }
     };
     (finalize $t: ty) => {
-        GCRTTI {
-            visit_references: {
-                extern "C" fn visit(data: *mut u8, trace: TracerPtr) {
-                    unsafe {
-                        (*data.add(8).cast::<$t>()).visit_references(&mut *core::mem::transmute::<
-                            [usize; 2],
-                            *mut dyn Tracer,
-                        >(
-                            trace.tracer
-                        ));
-                    }
-                }
-                visit
-            },
-            finalizer: Some({
-                extern "C" fn fin(data: *mut u8) {
-                    unsafe {
-                        core::ptr::drop_in_place(data.add(8).cast::<$t>());
-                    }
-                }
-                fin
-            }),
+        $crate::object::GCRTTI {
+            visit_references: $crate::object::rtti_visit_references::<$t>,
+            finalizer: Some($crate::object::rtti_finalize::<$t>),
             needs_finalization: true,
-            heap_size: {
-                extern "C" fn size(data: *mut u8) -> usize {
-                    unsafe { (*data.add(8).cast::<$t>()).heap_size() }
-                }
-                size
-            },
+            conservative: false,
+            heap_size: $crate::object::rtti_heap_size::<$t>,
+            alignment: core::mem::align_of::<$t>(),
+            notify_relocation: false,
+        }
+    };
+    (unsized $t: ty) => {
+        $crate::object::GCRTTI {
+            visit_references: $crate::object::rtti_unsized_visit_references::<$t>,
+            finalizer: Some($crate::object::rtti_unsized_finalize::<$t>),
+            needs_finalization: true,
+            conservative: false,
+            heap_size: $crate::object::rtti_unsized_heap_size::<$t>,
+            alignment: 0,
+            notify_relocation: false,
+        }
+    };
+}
+
+/// `visit_references` entry of RTTI created by [make_rtti_for]. Functions are generic instead of being defined
+/// inside of the macro so RTTI can be created for generic types.
+pub extern "C" fn rtti_visit_references<T: HeapObject>(data: *mut u8, trace: TracerPtr) {
+    unsafe {
+        (*T::from_data(data.add(core::mem::size_of::<RawGc>()))).visit_references(
+            &mut *core::mem::transmute::<[usize; 2], *mut dyn Tracer>(trace.tracer),
+        );
+    }
+}
+
+/// `finalizer` entry of RTTI created by [make_rtti_for].
+pub extern "C" fn rtti_finalize<T: HeapObject>(data: *mut u8) {
+    unsafe {
+        core::ptr::drop_in_place(T::from_data(data.add(core::mem::size_of::<RawGc>())));
+    }
+}
+
+/// `heap_size` entry of RTTI created by [make_rtti_for]. Padding of over-aligned value is included.
+pub extern "C" fn rtti_heap_size<T: HeapObject>(data: *mut u8) -> usize {
+    unsafe {
+        let value = T::from_data(data.add(core::mem::size_of::<RawGc>()));
+        (*value).heap_size() + (value as usize - data as usize)
+    }
+}
+
+/// `visit_references` entry of RTTI created by `make_rtti_for!(unsized T)`.
+pub extern "C" fn rtti_unsized_visit_references<T: HeapObject + GcPointee + ?Sized>(
+    data: *mut u8,
+    trace: TracerPtr,
+) {
+    unsafe {
+        (*T::from_data(data.add(core::mem::size_of::<RawGc>()))).visit_references(
+            &mut *core::mem::transmute::<[usize; 2], *mut dyn Tracer>(trace.tracer),
+        );
+    }
+}
+
+/// `finalizer` entry of RTTI created by `make_rtti_for!(unsized T)`.
+pub extern "C" fn rtti_unsized_finalize<T: HeapObject + GcPointee + ?Sized>(data: *mut u8) {
+    unsafe {
+        core::ptr::drop_in_place(T::from_data(data.add(core::mem::size_of::<RawGc>())));
+    }
+}
+
+/// `heap_size` entry of RTTI created by `make_rtti_for!(unsized T)`.
+pub extern "C" fn rtti_unsized_heap_size<T: HeapObject + GcPointee + ?Sized>(
+    data: *mut u8,
+) -> usize {
+    unsafe {
+        let header_size = core::mem::size_of::<RawGc>();
+        (*T::from_data(data.add(header_size))).heap_size() + header_size + T::METADATA_SIZE
+    }
+}
+
+/// Types that can be pointed to by [Gc]. Implemented for sized types, slices, `str` and trait objects registered
+/// with [impl_heap_object_for_dyn].
+///
+/// Objects of dynamically sized types store pointer metadata (length or vtable) in one word right after the object
+/// header and the value after it. Such objects use RTTI created by `make_rtti_for!(unsized T)`. The value is only
+/// aligned to 8 bytes, values with larger alignment are rejected when allocated.
+///
+/// Value of sized type with alignment above 8 is placed at the first aligned address after the header and one word
+/// that holds distance from the header to the value.
+///
+/// # Safety
+/// `from_data` must return pointer to the value that was stored with metadata returned by `metadata`.
+pub unsafe trait GcPointee {
+    /// Size of metadata stored between object header and the value.
+    const METADATA_SIZE: usize;
+    /// Metadata of `value` to store in the object.
+    fn metadata(value: *const Self) -> usize;
+    /// Pointer to the value of object with data (memory after header) at `data`.
+    ///
+    /// # Safety
+    /// `data` must point to data of an object of this type.
+    unsafe fn from_data(data: *mut u8) -> *mut Self;
+    /// Header of object that contains `value`.
+    ///
+    /// # Safety
+    /// `value` must point to the value of an object of this type.
+    unsafe fn header_of(value: *const Self) -> *mut RawGc {
+        value
+            .cast::<u8>()
+            .sub(core::mem::size_of::<RawGc>() + Self::METADATA_SIZE) as *mut RawGc
+    }
+}
+
+unsafe impl<T> GcPointee for T {
+    const METADATA_SIZE: usize = 0;
+    fn metadata(_: *const Self) -> usize {
+        0
+    }
+    unsafe fn from_data(data: *mut u8) -> *mut Self {
+        let align = core::mem::align_of::<T>();
+        if align > 8 {
+            align_usize(data as usize + 8, align) as *mut T
+        } else {
+            data.cast()
+        }
+    }
+    unsafe fn header_of(value: *const Self) -> *mut RawGc {
+        if core::mem::align_of::<T>() > 8 {
+            let offset = value.cast::<usize>().sub(1).read();
+            value.cast::<u8>().sub(offset) as *mut RawGc
+        } else {
+            value.cast::<u8>().sub(core::mem::size_of::<RawGc>()) as *mut RawGc
+        }
+    }
+}
+
+unsafe impl<T> GcPointee for [T] {
+    const METADATA_SIZE: usize = 8;
+    fn metadata(value: *const Self) -> usize {
+        unsafe { core::mem::transmute::<*const [T], [usize; 2]>(value)[1] }
+    }
+    unsafe fn from_data(data: *mut u8) -> *mut Self {
+        core::ptr::slice_from_raw_parts_mut(data.add(8).cast::<T>(), data.cast::<usize>().read())
+    }
+}
+
+unsafe impl GcPointee for str {
+    const METADATA_SIZE: usize = 8;
+    fn metadata(value: *const Self) -> usize {
+        <[u8]>::metadata(value as *const [u8])
+    }
+    unsafe fn from_data(data: *mut u8) -> *mut Self {
+        <[u8]>::from_data(data) as *mut str
+    }
+}
+
+impl<T: Trace> HeapObject for [T] {
+    const RTTI: GCRTTI = GCRTTI {
+        needs_finalization: core::mem::needs_drop::<T>(),
+        ..make_rtti_for!(unsized Self)
+    };
+    fn visit_references(&mut self, tracer: &mut dyn Tracer) {
+        self.trace(tracer);
+    }
+}
+
+impl HeapObject for str {
+    const RTTI: GCRTTI = GCRTTI {
+        needs_finalization: false,
+        ..make_rtti_for!(unsized Self)
+    };
+    const NO_SCAN: bool = true;
+}
+
+/// Implement [GcPointee] and [HeapObject] for trait object `dyn Trait` so `Gc<dyn Trait>` can be allocated with
+/// [immix_alloc_unsize](crate::immix_alloc_unsize). `Trait` must have [Trace] as supertrait, references are traced
+/// with it.
+///
+/// ```ignore
+/// trait Callable: Trace {
+///     fn call(&self, args: &[Value]) -> Value;
+/// }
+/// impl_heap_object_for_dyn!(dyn Callable);
+/// ```
+#[macro_export]
+macro_rules! impl_heap_object_for_dyn {
+    ($t: ty) => {
+        unsafe impl $crate::object::GcPointee for $t {
+            const METADATA_SIZE: usize = 8;
+            fn metadata(value: *const Self) -> usize {
+                unsafe { core::mem::transmute::<*const Self, [usize; 2]>(value)[1] }
+            }
+            unsafe fn from_data(data: *mut u8) -> *mut Self {
+                core::mem::transmute::<[usize; 2], *mut Self>([
+                    data.add(8) as usize,
+                    data.cast::<usize>().read(),
+                ])
+            }
+        }
+
+        impl $crate::object::HeapObject for $t {
+            const RTTI: $crate::object::GCRTTI = $crate::make_rtti_for!(unsized $t);
+            fn visit_references(&mut self, tracer: &mut dyn $crate::object::Tracer) {
+                $crate::object::Trace::trace(self, tracer);
+            }
         }
     };
 }
@@ -100,6 +277,9 @@ macro_rules! make_rtti_for {
 /// to check for resurrected objects.
 pub trait HeapObject {
     const RTTI: GCRTTI;
+    /// Set to true if this type never contains references into GC heap. Such objects are allocated
+    /// in separate blocks and are never scanned by GC.
+    const NO_SCAN: bool = false;
     /// Visit each field in this type.
     ///
     ///
@@ -113,8 +293,8 @@ pub trait HeapObject {
     ///
     /// ### Safety
     /// Some types (like `Gc`) need special actions taken when they're traced,
-    /// but those are easily handled: just invoke `visit_references` on `Gc`,
-    /// and it will be properly passed to `tracer`.
+    /// but those are easily handled: just invoke [Trace::trace] on `Gc`,
+    /// and it will be properly passed to `tracer`. `#[derive(HeapObject)]` does this for every field.
     ///
     /// ## Always Permitted
     /// - Reading your own memory (includes iteration)
@@ -140,7 +320,8 @@ pub trait HeapObject {
     }
     /// Returns *real* size of object on the heap. For static objects it is equal to `size_of_val(self)` but
     /// if you need some variable sized object on heap (i.e array) you have to change this function impl so
-    /// it will return proper size for array (example: `self.len() * size_of::<T>() + size_of::<Array<T>>()`)
+    /// it will return proper size for array (example: `self.len() * size_of::<T>() + size_of::<Array<T>>()`).
+    /// [GcArray](crate::array::GcArray) is implemented this way.
     fn heap_size(&self) -> usize {
         core::mem::size_of_val(self)
     }
@@ -155,7 +336,187 @@ pub trait HeapObject {
         false
     }
 }
-pub fn object_ty_of<T: HeapObject>(_: *const T) -> usize {
+#[cfg(feature = "derive")]
+pub use libimmixcons_derive::HeapObject;
+
+/// Values that might contain references into GC heap. `#[derive(HeapObject)]` traces every field with this trait.
+pub trait Trace {
+    /// Pass every reference contained in this value to `tracer`.
+    fn trace(&mut self, tracer: &mut dyn Tracer);
+}
+
+impl<T: HeapObject + ?Sized> Trace for Gc<T> {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        tracer.trace(&mut self.ptr);
+    }
+}
+
+impl<T: Trace> Trace for Option<T> {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        if let Some(value) = self {
+            value.trace(tracer);
+        }
+    }
+}
+
+impl<T: Trace> Trace for [T] {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        for value in self.iter_mut() {
+            value.trace(tracer);
+        }
+    }
+}
+
+impl<T: Trace, const N: usize> Trace for [T; N] {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        self[..].trace(tracer);
+    }
+}
+
+impl<T: Trace> Trace for alloc::vec::Vec<T> {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        self[..].trace(tracer);
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Trace + ?Sized> Trace for alloc::boxed::Box<T> {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        (**self).trace(tracer);
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Trace> Trace for alloc::collections::VecDeque<T> {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        for value in self.iter_mut() {
+            value.trace(tracer);
+        }
+    }
+}
+
+/// Only values are traced: keys are immutable and can not be updated when objects are moved, so they must not
+/// contain references into GC heap.
+#[cfg(feature = "alloc")]
+impl<K, V: Trace> Trace for alloc::collections::BTreeMap<K, V> {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        for value in self.values_mut() {
+            value.trace(tracer);
+        }
+    }
+}
+
+/// Only values are traced: keys are immutable and can not be updated when objects are moved, so they must not
+/// contain references into GC heap.
+#[cfg(feature = "std")]
+impl<K, V: Trace, S> Trace for std::collections::HashMap<K, V, S> {
+    fn trace(&mut self, tracer: &mut dyn Tracer) {
+        for value in self.values_mut() {
+            value.trace(tracer);
+        }
+    }
+}
+
+impl<T: ?Sized> Trace for PhantomData<T> {
+    fn trace(&mut self, _: &mut dyn Tracer) {}
+}
+
+macro_rules! impl_trace_noop {
+    ($($t: ty),*) => {
+        $(
+            impl Trace for $t {
+                fn trace(&mut self, _: &mut dyn Tracer) {}
+            }
+        )*
+    };
+}
+
+impl_trace_noop!(
+    u8,
+    i8,
+    u16,
+    i16,
+    u32,
+    i32,
+    u64,
+    i64,
+    u128,
+    i128,
+    usize,
+    isize,
+    f32,
+    f64,
+    bool,
+    char,
+    (),
+    &'static str
+);
+
+impl_trace_noop!(alloc::string::String);
+
+macro_rules! impl_trace_tuple {
+    ($($name: ident)+) => {
+        impl<$($name: Trace),+> Trace for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn trace(&mut self, tracer: &mut dyn Tracer) {
+                let ($($name,)+) = self;
+                $($name.trace(tracer);)+
+            }
+        }
+    };
+}
+
+impl_trace_tuple!(A);
+impl_trace_tuple!(A B);
+impl_trace_tuple!(A B C);
+impl_trace_tuple!(A B C D);
+
+/// Implement [HeapObject] with [Trace] so values of these types can be allocated directly, i.e `Gc<Vec<Gc<T>>>`.
+/// Finalizer runs only if the type needs drop.
+macro_rules! impl_heap_object_for_trace {
+    ($([$($params: tt)*] $t: ty),* $(,)?) => {
+        $(
+            impl<$($params)*> HeapObject for $t {
+                const RTTI: GCRTTI = GCRTTI {
+                    needs_finalization: core::mem::needs_drop::<Self>(),
+                    ..make_rtti_for!(Self)
+                };
+                fn visit_references(&mut self, tracer: &mut dyn Tracer) {
+                    Trace::trace(self, tracer);
+                }
+            }
+        )*
+    };
+}
+
+impl_heap_object_for_trace!(
+    [T: HeapObject + ?Sized] Gc<T>,
+    [T: Trace] Option<T>,
+    [T: Trace, const N: usize] [T; N],
+    [A: Trace] (A,),
+    [A: Trace, B: Trace] (A, B),
+    [A: Trace, B: Trace, C: Trace] (A, B, C),
+    [A: Trace, B: Trace, C: Trace, D: Trace] (A, B, C, D),
+);
+
+#[cfg(feature = "alloc")]
+impl_heap_object_for_trace!(
+    [T: Trace] alloc::vec::Vec<T>,
+    [T: Trace] alloc::boxed::Box<T>,
+    [T: Trace] alloc::collections::VecDeque<T>,
+    [K, V: Trace] alloc::collections::BTreeMap<K, V>,
+);
+
+#[cfg(feature = "std")]
+impl_heap_object_for_trace!([K, V: Trace, S] std::collections::HashMap<K, V, S>);
+
+#[cfg(feature = "alloc")]
+impl HeapObject for alloc::string::String {
+    const RTTI: GCRTTI = make_rtti_for!(finalize alloc::string::String);
+    const NO_SCAN: bool = true;
+}
+
+pub fn object_ty_of<T: HeapObject + ?Sized>(_: *const T) -> usize {
     &T::RTTI as *const GCRTTI as usize
 }
 
@@ -169,7 +530,54 @@ impl RawGc {
         unsafe { &*(self.vtable() as *mut GCRTTI) }
     }
     pub fn object_size(&self) -> usize {
-        align_usize((self.rtti().heap_size)(self as *const Self as *mut u8), 16)
+        if self.is_hashed_and_moved() {
+            align_usize(self.hash_slot_offset() + 8, 16)
+        } else {
+            align_usize((self.rtti().heap_size)(self as *const Self as *mut u8), 16)
+        }
+    }
+
+    /// Offset of the slot that holds identity hash of object that was moved after it was hashed.
+    pub fn hash_slot_offset(&self) -> usize {
+        align_usize((self.rtti().heap_size)(self as *const Self as *mut u8), 8)
+    }
+
+    /// Return stable identity hash of this object. Hash is the address of object when it is hashed for the first
+    /// time. If object is evacuated after that the hash is stored in a slot appended to the copy.
+    ///
+    /// Objects can be hashed by several threads at once so the hashed bit is set atomically.
+    pub fn identity_hash(&self) -> usize {
+        if self.is_hashed_and_moved() {
+            unsafe {
+                (self as *const Self as *const u8)
+                    .add(self.hash_slot_offset())
+                    .cast::<usize>()
+                    .read()
+            }
+        } else {
+            self.vtable.atomic_set_bit(HASHED_BIT);
+            self as *const Self as usize
+        }
+    }
+
+    pub fn is_hashed(&self) -> bool {
+        self.vtable.bit_is_set(HASHED_BIT)
+    }
+
+    pub fn is_hashed_and_moved(&self) -> bool {
+        self.vtable.bit_is_set(HASHED_AND_MOVED_BIT)
+    }
+
+    /// Store identity hash `hash` of object that is moved to this copy after it was hashed.
+    ///
+    /// # Safety
+    /// Object must have space for the hash slot after it.
+    pub unsafe fn set_moved_hash(&mut self, hash: usize) {
+        (self as *mut Self as *mut u8)
+            .add(self.hash_slot_offset())
+            .cast::<usize>()
+            .write(hash);
+        self.vtable.set_bit(HASHED_AND_MOVED_BIT);
     }
 
     pub fn data(&self) -> *mut u8 {
@@ -186,9 +594,17 @@ impl RawGc {
     pub fn new(vtable: usize) -> Self {
         Self {
             vtable: TaggedPointer::new(vtable as *mut _),
+            #[cfg(feature = "header-word")]
+            word: AtomicUsize::new(0),
         }
     }
 
+    /// Header word reserved for users of the collector. See [RawGc::word].
+    #[cfg(feature = "header-word")]
+    pub fn header_word(&self) -> &AtomicUsize {
+        &self.word
+    }
+
     pub fn mark(&mut self, mark: bool) -> bool {
         let prev = self.vtable.bit_is_set(0);
 
@@ -229,6 +645,20 @@ impl RawGc {
         debug_assert!(!self.vtable.untagged().is_null());
         self.vtable.untagged() as usize
     }
+
+    /// Replace RTTI of this object keeping mark, pin and hash bits.
+    pub fn set_vtable(&mut self, vtable: usize) {
+        let mark = self.get_mark();
+        let pinned = self.is_pinned();
+        let hashed = self.is_hashed();
+        let hashed_and_moved = self.is_hashed_and_moved();
+        self.vtable = TaggedPointer::new(vtable as *mut _);
+        self.vtable.set_bit_x(mark, 0);
+        self.vtable.set_bit_x(pinned, 2);
+        self.vtable.set_bit_x(hashed, HASHED_BIT);
+        self.vtable
+            .set_bit_x(hashed_and_moved, HASHED_AND_MOVED_BIT);
+    }
 }
 /// rounds the given value `val` up to the nearest multiple
 /// of `align`.
@@ -249,41 +679,133 @@ pub fn align_usize(value: usize, align: usize) -> usize {
 /// and not some arbitrary bits that you've decided to heap allocate.
 ///
 /// NOTE: GC is smart enough to find out that for example reference like this `&*my_gc`
-/// on stack points into some object by aligning down to 16 bytes so you do not have to worry about it.
+/// on stack points into some object, including values of slices, trait objects and over-aligned types, so you do
+/// not have to worry about it.
 pub struct Gc<T: HeapObject + ?Sized> {
     pub ptr: NonNull<RawGc>,
     pub marker: PhantomData<T>,
 }
 
-impl<T: HeapObject> Deref for Gc<T> {
+impl<T: HeapObject + GcPointee + ?Sized> Deref for Gc<T> {
     type Target = T;
     fn deref(&self) -> &T {
-        unsafe { &*(&*self.ptr.as_ptr()).data().cast::<T>() }
+        unsafe { &*T::from_data((&*self.ptr.as_ptr()).data()) }
     }
 }
 
-impl<T: HeapObject> DerefMut for Gc<T> {
+/// Unchecked mutable access. Disabled by `strict-mutability` feature, use [GcCell](crate::cell::GcCell),
+/// [GcRefCell](crate::cell::GcRefCell) or [Gc::get_mut_unchecked] instead.
+#[cfg(not(feature = "strict-mutability"))]
+impl<T: HeapObject + GcPointee + ?Sized> core::ops::DerefMut for Gc<T> {
     fn deref_mut(&mut self) -> &mut T {
-        unsafe { &mut *(&*self.ptr.as_ptr()).data().cast::<T>() }
+        unsafe { self.get_mut_unchecked() }
     }
 }
-impl<T: HeapObject + ?Sized> Gc<T> {
+
+impl<T: HeapObject + GcPointee + ?Sized> Gc<T> {
+    /// Return mutable reference to the object. Nothing prevents other copies of this `Gc` from accessing the
+    /// object at the same time and no write barrier is invoked.
+    ///
+    /// # Safety
+    /// Caller must guarantee that the object is not accessed through other references while the returned
+    /// reference is alive.
+    pub unsafe fn get_mut_unchecked(&mut self) -> &mut T {
+        &mut *T::from_data((&*self.ptr.as_ptr()).data())
+    }
+
     pub fn from_raw(ptr: *const T) -> Self {
         Self {
             marker: PhantomData,
-            ptr: unsafe { NonNull::new_unchecked(ptr.cast::<RawGc>().sub(1) as *mut RawGc) },
+            ptr: unsafe { NonNull::new_unchecked(T::header_of(ptr)) },
         }
     }
+}
 
+impl<T: HeapObject + ?Sized> Gc<T> {
     pub fn get_rtti(&self) -> &'static GCRTTI {
         unsafe { (*self.ptr.as_ptr()).rtti() }
     }
+
+    /// Header word of the object reserved for users of the collector. See [RawGc::word].
+    #[cfg(feature = "header-word")]
+    pub fn header_word(&self) -> &AtomicUsize {
+        unsafe { (*self.ptr.as_ptr()).header_word() }
+    }
+}
+
+/// Identity hash of `object` that does not change when object is moved by GC.
+#[no_mangle]
+pub extern "C" fn immix_identity_hash(object: &crate::GCObject) -> usize {
+    unsafe { (*(object as *const _ as *const RawGc)).identity_hash() }
 }
 
 #[no_mangle]
 pub extern "C" fn immix_object_get_rtti(object: &crate::GCObject) -> &'static GCRTTI {
     unsafe { (*(object as *const _ as *const RawGc)).rtti() }
 }
+/// Load header word of `object`. Only available with `header-word` feature.
+#[cfg(feature = "header-word")]
+#[no_mangle]
+pub extern "C" fn immix_header_word_load(object: &crate::GCObject) -> usize {
+    unsafe {
+        (*(object as *const _ as *const RawGc))
+            .word
+            .load(Ordering::Acquire)
+    }
+}
+
+/// Store `value` to header word of `object`.
+#[cfg(feature = "header-word")]
+#[no_mangle]
+pub extern "C" fn immix_header_word_store(object: &crate::GCObject, value: usize) {
+    unsafe {
+        (*(object as *const _ as *const RawGc))
+            .word
+            .store(value, Ordering::Release)
+    }
+}
+
+/// Replace header word of `object` with `new` if it is equal to `current`. Returns previous value, the exchange
+/// succeeded if it is equal to `current`.
+#[cfg(feature = "header-word")]
+#[no_mangle]
+pub extern "C" fn immix_header_word_compare_exchange(
+    object: &crate::GCObject,
+    current: usize,
+    new: usize,
+) -> usize {
+    unsafe {
+        match (*(object as *const _ as *const RawGc))
+            .word
+            .compare_exchange(current, new, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(previous) | Err(previous) => previous,
+        }
+    }
+}
+
+/// Set bits of `bits` in header word of `object`. Returns previous value.
+#[cfg(feature = "header-word")]
+#[no_mangle]
+pub extern "C" fn immix_header_word_fetch_or(object: &crate::GCObject, bits: usize) -> usize {
+    unsafe {
+        (*(object as *const _ as *const RawGc))
+            .word
+            .fetch_or(bits, Ordering::AcqRel)
+    }
+}
+
+/// Clear bits that are not set in `mask` in header word of `object`. Returns previous value.
+#[cfg(feature = "header-word")]
+#[no_mangle]
+pub extern "C" fn immix_header_word_fetch_and(object: &crate::GCObject, mask: usize) -> usize {
+    unsafe {
+        (*(object as *const _ as *const RawGc))
+            .word
+            .fetch_and(mask, Ordering::AcqRel)
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub struct TracerPtr {
@@ -297,6 +819,13 @@ impl TracerPtr {
                 .trace(core::mem::transmute(val));
         }
     }
+
+    pub fn trace_tagged(self, slot: &mut usize, tag_mask: usize) {
+        unsafe {
+            (*core::mem::transmute::<[usize; 2], *mut dyn Tracer>(self.tracer))
+                .trace_tagged(slot, tag_mask);
+        }
+    }
 }
 
 #[no_mangle]
@@ -307,11 +836,24 @@ pub extern "C" fn tracer_trace(p: TracerPtr, gc_val: *mut *mut RawGc) {
     }
 }
 
+/// Trace tagged reference stored in `slot`. Bits of `tag_mask` are stripped before tracing and
+/// reapplied to the forwarded pointer after evacuation. `slot` must hold a tagged pointer or a tagged null,
+/// values that are not pointers must be skipped by the caller.
+///
+/// # Safety
+/// `slot` must be valid for reads and writes.
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+pub unsafe extern "C" fn tracer_trace_tagged(p: TracerPtr, slot: *mut usize, tag_mask: usize) {
+    p.trace_tagged(&mut *slot, tag_mask);
+}
+
 macro_rules! impl_for_prim {
     ($($t: ident)*) => {
         $(
             impl HeapObject for $t {
                 const RTTI: GCRTTI = make_rtti_for!($t);
+                const NO_SCAN: bool = true;
             }
         )*
     };
@@ -349,6 +891,16 @@ pub struct GCRTTI {
     pub needs_finalization: bool,
     /// Object finalizer. Invoked when object is dead.
     pub finalizer: Option<extern "C" fn(*mut u8)>,
+    /// If set to true object body is scanned word by word for conservative references in addition to `visit_references`.
+    /// Objects found this way are pinned and evacuation is disabled while such objects are alive.
+    pub conservative: bool,
+    /// Alignment of object value. If it is above 8 the value is placed at the first aligned address after the header
+    /// and one word that holds distance from the header to the value, evacuation keeps the alignment. Zero means
+    /// default alignment of 8.
+    pub alignment: usize,
+    /// If set to true moves of objects that use this RTTI are reported to callbacks registered with
+    /// `immix_register_relocation_callback`.
+    pub notify_relocation: bool,
 }
 
 #[repr(C)]
@@ -382,6 +934,22 @@ impl<T: HeapObject + ?Sized> Clone for Gc<T> {
     }
 }
 
+/// Identity comparison: two `Gc`s are equal if they point to the same object.
+impl<T: HeapObject + ?Sized> PartialEq for Gc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl<T: HeapObject + ?Sized> Eq for Gc<T> {}
+
+/// Hashes identity of the object, see [RawGc::identity_hash].
+impl<T: HeapObject + ?Sized> core::hash::Hash for Gc<T> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        state.write_usize(unsafe { (*self.ptr.as_ptr()).identity_hash() });
+    }
+}
+
 static mut NOOP_SINK: usize = 0;
 
 #[inline]
@@ -0,0 +1,388 @@
+//! Allocation sampling profiler.
+//!
+//! When sampling interval is set with [immix_set_sampling_interval] an allocation is sampled each time another
+//! `interval` bytes were allocated. Sample records RTTI, size and backtrace of the allocation and weight: number of
+//! allocated bytes it represents. Samples can be exported as folded stacks for flame graph tools
+//! ([immix_write_folded_profile]) or as pprof profile ([immix_write_pprof_profile]). In both formats RTTI of the
+//! allocated object is the leaf frame so allocations are grouped by type and call site.
+//!
+//! With sampling disabled the allocation path only checks [Profiler::enabled].
+use crate::{util::*, SPACE};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "threaded")]
+use parking_lot::lock_api::RawMutex;
+
+/// Maximum number of frames recorded for a sample.
+const MAX_FRAMES: usize = 64;
+
+/// Sampled allocation.
+#[derive(Clone, Debug)]
+pub struct AllocationSample {
+    /// Address of object RTTI.
+    pub rtti: usize,
+    /// Size of the object in bytes.
+    pub size: usize,
+    /// Number of allocated bytes this sample represents.
+    pub weight: usize,
+    /// Return addresses of the allocation backtrace, innermost frame first.
+    pub frames: Vec<usize>,
+}
+
+pub(crate) struct Profiler {
+    /// True if sampling is enabled. This is the only thing checked on allocation path when sampling is disabled.
+    pub(crate) enabled: AtomicBool,
+    interval: usize,
+    /// Bytes left until next sample.
+    countdown: isize,
+    samples: Vec<AllocationSample>,
+    #[cfg(feature = "threaded")]
+    lock: crate::util::locks::mutex::Mutex,
+}
+
+#[cfg(all(unix, any(target_env = "gnu", target_os = "macos")))]
+extern "C" {
+    fn backtrace(buffer: *mut *mut libc::c_void, size: libc::c_int) -> libc::c_int;
+}
+
+/// Capture return addresses of the current thread skipping `skip` innermost frames.
+#[inline(never)]
+fn capture_backtrace(skip: usize) -> Vec<usize> {
+    #[cfg(all(unix, any(target_env = "gnu", target_os = "macos")))]
+    unsafe {
+        let mut frames = [0usize; MAX_FRAMES];
+        let count = backtrace(frames.as_mut_ptr().cast(), MAX_FRAMES as _) as usize;
+        // skip this function too
+        frames[count.min(skip + 1)..count].to_vec()
+    }
+    #[cfg(not(all(unix, any(target_env = "gnu", target_os = "macos"))))]
+    {
+        let _ = skip;
+        Vec::new()
+    }
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            interval: 0,
+            countdown: 0,
+            samples: Vec::new(),
+            #[cfg(feature = "threaded")]
+            lock: crate::util::locks::mutex::Mutex::new(),
+        }
+    }
+
+    fn lock(&self) {
+        #[cfg(feature = "threaded")]
+        {
+            self.lock.lock();
+        }
+    }
+
+    fn unlock(&self) {
+        #[cfg(feature = "threaded")]
+        unsafe {
+            self.lock.unlock();
+        }
+    }
+
+    fn set_interval(&mut self, interval: usize) {
+        self.lock();
+        self.interval = interval;
+        self.countdown = interval as isize;
+        self.enabled.store(interval != 0, Ordering::Release);
+        self.unlock();
+    }
+
+    /// Account `size` bytes allocated for object with `rtti` and sample the allocation if interval is reached.
+    #[inline(never)]
+    pub(crate) fn allocated(&mut self, size: usize, rtti: usize) {
+        self.lock();
+        if self.interval != 0 {
+            self.countdown -= size as isize;
+            if self.countdown <= 0 {
+                let interval = self.interval as isize;
+                let intervals = 1 + (-self.countdown) / interval;
+                self.countdown += intervals * interval;
+                self.samples.push(AllocationSample {
+                    rtti,
+                    size,
+                    weight: intervals as usize * self.interval,
+                    // skip `allocated`
+                    frames: capture_backtrace(1),
+                });
+            }
+        }
+        self.unlock();
+    }
+
+    fn interval(&self) -> usize {
+        self.lock();
+        let interval = self.interval;
+        self.unlock();
+        interval
+    }
+
+    fn samples(&self) -> Vec<AllocationSample> {
+        self.lock();
+        let samples = self.samples.clone();
+        self.unlock();
+        samples
+    }
+
+    fn clear(&mut self) {
+        self.lock();
+        self.samples.clear();
+        self.unlock();
+    }
+}
+
+/// Name of function that contains return address `pc` or its address if it is unknown.
+fn frame_name(pc: usize) -> String {
+    #[cfg(unix)]
+    unsafe {
+        if let Some(name) = symbol_name((pc - 1) as *const u8) {
+            return String::from_utf8_lossy(name).into_owned();
+        }
+    }
+    format!("{:#x}", pc)
+}
+
+/// Name of RTTI at `rtti` or its address if it is unknown.
+fn rtti_name(rtti: usize) -> String {
+    #[cfg(unix)]
+    unsafe {
+        if let Some(name) = symbol_name(rtti as *const u8) {
+            return String::from_utf8_lossy(name).into_owned();
+        }
+    }
+    format!("rtti@{:#x}", rtti)
+}
+
+/// Set sampling interval in bytes. Allocation is sampled every time another `interval` bytes are allocated.
+/// Zero disables sampling, already recorded samples are kept.
+#[no_mangle]
+pub extern "C" fn immix_set_sampling_interval(interval: usize) {
+    unsafe { (*SPACE).profiler.set_interval(interval) }
+}
+
+/// Drop all recorded allocation samples.
+#[no_mangle]
+pub extern "C" fn immix_clear_allocation_samples() {
+    unsafe { (*SPACE).profiler.clear() }
+}
+
+/// Return copy of recorded allocation samples.
+pub fn allocation_samples() -> Vec<AllocationSample> {
+    unsafe { (*SPACE).profiler.samples() }
+}
+
+/// Fold `samples` into `stack weight` lines. Frames are separated by `;`, the outermost frame comes first and RTTI
+/// of the allocated object is the last frame.
+pub fn folded_stacks(samples: &[AllocationSample]) -> String {
+    let mut stacks = BTreeMap::new();
+    for sample in samples {
+        let mut stack = String::new();
+        for &pc in sample.frames.iter().rev() {
+            stack.push_str(&frame_name(pc).replace(';', ":"));
+            stack.push(';');
+        }
+        stack.push_str(&rtti_name(sample.rtti).replace(';', ":"));
+        *stacks.entry(stack).or_insert(0) += sample.weight;
+    }
+    let mut out = String::new();
+    for (stack, weight) in stacks {
+        let _ = writeln!(out, "{} {}", stack, weight);
+    }
+    out
+}
+
+/// Write recorded samples to file at `path` as folded stacks. Each line contains frames separated by `;` (the
+/// outermost first, RTTI of allocated object last) and number of allocated bytes.
+///
+/// ## Return value
+/// Returns false if file could not be written.
+///
+/// # Safety
+/// `path` must point to a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn immix_write_folded_profile(path: *const libc::c_char) -> bool {
+    let mut out = match FileWriter::create(path) {
+        Some(out) => out,
+        None => return false,
+    };
+    out.write_bytes(folded_stacks(&allocation_samples()).as_bytes());
+    out.finish()
+}
+
+/// Minimal protocol buffers encoder.
+struct Proto {
+    buf: Vec<u8>,
+}
+
+impl Proto {
+    fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn uint(&mut self, field: u64, value: u64) {
+        self.varint(field << 3);
+        self.varint(value);
+    }
+
+    fn bytes(&mut self, field: u64, bytes: &[u8]) {
+        self.varint(field << 3 | 2);
+        self.varint(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn packed(&mut self, field: u64, values: &[u64]) {
+        let mut packed = Proto::new();
+        for &value in values {
+            packed.varint(value);
+        }
+        self.bytes(field, &packed.buf);
+    }
+
+    fn message(&mut self, field: u64, build: impl FnOnce(&mut Proto)) {
+        let mut message = Proto::new();
+        build(&mut message);
+        self.bytes(field, &message.buf);
+    }
+}
+
+/// String table of pprof profile.
+struct Strings {
+    strings: Vec<String>,
+    indices: BTreeMap<String, u64>,
+}
+
+impl Strings {
+    fn intern(&mut self, s: &str) -> u64 {
+        if let Some(&ix) = self.indices.get(s) {
+            return ix;
+        }
+        let ix = self.strings.len() as u64;
+        self.strings.push(s.into());
+        self.indices.insert(s.into(), ix);
+        ix
+    }
+}
+
+/// Encode `samples` as uncompressed pprof `Profile` message with `alloc_objects` and `alloc_space` sample types.
+/// Samples are labelled with `type` label set to RTTI name.
+pub fn pprof_profile(samples: &[AllocationSample], interval: usize) -> Vec<u8> {
+    let mut strings = Strings {
+        strings: Vec::new(),
+        indices: BTreeMap::new(),
+    };
+    strings.intern("");
+    let mut profile = Proto::new();
+    for &(ty, unit) in [("alloc_objects", "count"), ("alloc_space", "bytes")].iter() {
+        let ty = strings.intern(ty);
+        let unit = strings.intern(unit);
+        profile.message(1, |value_type| {
+            value_type.uint(1, ty);
+            value_type.uint(2, unit);
+        });
+    }
+    // Locations are keyed by return address, RTTI pseudo frames by RTTI name.
+    let mut locations = BTreeMap::new();
+    let mut rtti_locations = BTreeMap::new();
+    let mut functions = BTreeMap::new();
+    let mut location_list = Vec::new();
+    let mut function_list = Vec::new();
+    let mut function_id = |name: String, strings: &mut Strings, list: &mut Vec<(u64, u64)>| {
+        let next = functions.len() as u64 + 1;
+        *functions.entry(name.clone()).or_insert_with(|| {
+            list.push((next, strings.intern(&name)));
+            next
+        })
+    };
+    let type_key = strings.intern("type");
+    for sample in samples {
+        let name = rtti_name(sample.rtti);
+        let type_value = strings.intern(&name);
+        let mut ids = Vec::with_capacity(sample.frames.len() + 1);
+        let next = (location_list.len() + 1) as u64;
+        ids.push(*rtti_locations.entry(name.clone()).or_insert_with(|| {
+            let function = function_id(name, &mut strings, &mut function_list);
+            location_list.push((next, 0, function));
+            next
+        }));
+        for &pc in sample.frames.iter() {
+            let next = (location_list.len() + 1) as u64;
+            ids.push(*locations.entry(pc).or_insert_with(|| {
+                let function = function_id(frame_name(pc), &mut strings, &mut function_list);
+                location_list.push((next, pc as u64 - 1, function));
+                next
+            }));
+        }
+        let count = core::cmp::max(1, sample.weight / core::cmp::max(1, sample.size)) as u64;
+        profile.message(2, |message| {
+            message.packed(1, &ids);
+            message.packed(2, &[count, sample.weight as u64]);
+            message.message(3, |label| {
+                label.uint(1, type_key);
+                label.uint(2, type_value);
+            });
+        });
+    }
+    for &(id, address, function) in location_list.iter() {
+        profile.message(4, |location| {
+            location.uint(1, id);
+            location.uint(3, address);
+            location.message(4, |line| line.uint(1, function));
+        });
+    }
+    for &(id, name) in function_list.iter() {
+        profile.message(5, |function| {
+            function.uint(1, id);
+            function.uint(2, name);
+            function.uint(3, name);
+        });
+    }
+    let space = strings.intern("space");
+    let bytes = strings.intern("bytes");
+    for s in strings.strings.iter() {
+        profile.bytes(6, s.as_bytes());
+    }
+    profile.message(11, |period_type| {
+        period_type.uint(1, space);
+        period_type.uint(2, bytes);
+    });
+    profile.uint(12, interval as u64);
+    profile.buf
+}
+
+/// Write recorded samples to file at `path` as uncompressed pprof profile. `go tool pprof` reads such files directly.
+///
+/// ## Return value
+/// Returns false if file could not be written.
+///
+/// # Safety
+/// `path` must point to a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn immix_write_pprof_profile(path: *const libc::c_char) -> bool {
+    let mut out = match FileWriter::create(path) {
+        Some(out) => out,
+        None => return false,
+    };
+    let interval = (*SPACE).profiler.interval();
+    out.write_bytes(&pprof_profile(&allocation_samples(), interval));
+    out.finish()
+}
@@ -0,0 +1,32 @@
+//! Notifications about objects moved by evacuation.
+//!
+//! VMs often keep tables keyed by object address outside of GC heap (inline caches, interned strings). Such
+//! tables go stale when evacuation moves objects. Objects that use RTTI with `notify_relocation` set are recorded
+//! when they are moved and after the collection every callback registered with
+//! [immix_register_relocation_callback] receives all moves of the cycle at once, so a table can be rehashed in one
+//! pass. Callbacks are invoked before mutators are resumed and must not allocate in GC heap.
+use crate::GCObject;
+
+/// Object moved from `old` to `new` address.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Relocation {
+    pub old: *mut GCObject,
+    pub new: *mut GCObject,
+}
+
+/// Callback that receives `count` relocations that happened during GC cycle.
+pub type RelocationCallback =
+    extern "C" fn(data: *mut u8, relocations: *const Relocation, count: usize);
+
+/// Register callback that will be invoked after each GC cycle that moved objects with `notify_relocation` set in
+/// their RTTI.
+///
+///
+/// WARNING: There is no way to "unregister" this callback.
+#[no_mangle]
+pub extern "C" fn immix_register_relocation_callback(callback: RelocationCallback, data: *mut u8) {
+    unsafe {
+        (*crate::SPACE).relocation_callbacks.push((callback, data));
+    }
+}
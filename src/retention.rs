@@ -0,0 +1,188 @@
+//! Retention path queries.
+//!
+//! [find_retention_path] answers "why is this object alive?": it stops the world and runs breadth-first search from
+//! roots to the object. References are found the same way GC finds them but mark bits are not modified, so the
+//! query can be made at any point (i.e from a debugger with [immix_print_retention_path]).
+//!
+//! The caller of the query always holds the queried object on its stack or in registers, so conservatively found
+//! stack references to the object itself are used only if no other path reaches it. The object is then reported as
+//! referenced directly from the stack.
+use crate::{
+    collector, object::*, snapshot::RootSource, util::*, GCObject, Immix, StoppedWorld, SPACE,
+};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+/// Chain of references that keeps an object alive.
+#[derive(Clone, Debug)]
+pub struct RetentionPath {
+    /// Kind of the root that references first object in `objects`.
+    pub source: RootSource,
+    /// Address of the root or 0 if root was found conservatively.
+    pub slot: usize,
+    /// Objects from the root to the queried object. Each object references the next one.
+    pub objects: Vec<*mut RawGc>,
+}
+
+#[derive(Clone, Copy)]
+enum Parent {
+    Root(RootSource, usize),
+    Object(*mut RawGc),
+}
+
+impl Immix {
+    /// Find shortest path from roots to `target`. Must only be called while the world is stopped.
+    pub(crate) unsafe fn retention_path(
+        &mut self,
+        world: &StoppedWorld,
+        target: *mut RawGc,
+    ) -> Option<RetentionPath> {
+        let mut parents = BTreeMap::new();
+        let mut queue = VecDeque::new();
+        let mut stack_reference = false;
+        for (source, slot, object) in self.snapshot_roots(world) {
+            // Might be reference of the query itself, it is used only if `target` is not reachable otherwise.
+            if source == RootSource::Stack && slot == 0 && object == target {
+                stack_reference = true;
+                continue;
+            }
+            if object.is_null() || parents.contains_key(&object) {
+                continue;
+            }
+            parents.insert(object, Parent::Root(source, slot));
+            queue.push_back(object);
+        }
+        while let Some(object) = queue.pop_front() {
+            if object == target {
+                break;
+            }
+            for (_, child) in self.object_edges(object) {
+                if child.is_null() || parents.contains_key(&child) {
+                    continue;
+                }
+                parents.insert(child, Parent::Object(object));
+                queue.push_back(child);
+            }
+        }
+
+        if stack_reference && !parents.contains_key(&target) {
+            parents.insert(target, Parent::Root(RootSource::Stack, 0));
+        }
+        let mut objects = Vec::new();
+        let mut current = target;
+        loop {
+            objects.push(current);
+            match *parents.get(&current)? {
+                Parent::Object(parent) => current = parent,
+                Parent::Root(source, slot) => {
+                    objects.reverse();
+                    return Some(RetentionPath {
+                        source,
+                        slot,
+                        objects,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Find chain of objects that keeps object containing `object` alive. Returns `None` if `object` is not a heap
+/// object or it is not reachable from roots.
+pub fn find_retention_path(object: *mut RawGc) -> Option<RetentionPath> {
+    unsafe {
+        crate::util::save_regs();
+        let world = crate::stop_the_world_retry();
+        #[cfg(not(feature = "threaded"))]
+        {
+            (*SPACE).stack_end = crate::stack_pointer() as *mut u8;
+        }
+        let space = &mut *SPACE;
+        let path = collector::find_object(&*space.immix, &space.mos, &space.los, object.cast())
+            .and_then(|object| space.retention_path(&world, object));
+        crate::resume_the_world(world);
+        path
+    }
+}
+
+/// Find chain of objects that keeps `object` alive.
+///
+/// ## Inputs
+/// - `object`: Object to query.
+/// - `path`: Buffer that receives objects from the root to `object`. Might be null if `capacity` is zero.
+/// - `capacity`: Size of `path` buffer, objects that do not fit are not written.
+/// - `source`: Receives kind of the root (see `IMMIX_ROOT_*` constants). Might be null.
+///
+/// ## Return value
+/// Returns length of the chain or zero if `object` is not reachable from roots.
+///
+/// # Safety
+/// `path` must be valid for writes of `capacity` pointers and `source` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn immix_find_retention_path(
+    object: *mut GCObject,
+    path: *mut *mut GCObject,
+    capacity: usize,
+    source: *mut RootSource,
+) -> usize {
+    let retention = match find_retention_path(object.cast()) {
+        Some(retention) => retention,
+        None => return 0,
+    };
+    for (ix, &object) in retention.objects.iter().take(capacity).enumerate() {
+        path.add(ix).write(object.cast());
+    }
+    if !source.is_null() {
+        source.write(retention.source);
+    }
+    retention.objects.len()
+}
+
+#[cfg(unix)]
+extern "C" {
+    fn printf(c: *const i8, ...) -> i32;
+}
+
+/// Print chain of objects that keeps `object` alive with RTTI names of the objects. Meant to be called from a
+/// debugger. `object` is only looked up in the heap and printed, so any pointer can be passed.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn immix_print_retention_path(object: *mut GCObject) {
+    let retention = find_retention_path(object.cast());
+    #[cfg(unix)]
+    unsafe {
+        let retention = match retention {
+            Some(retention) => retention,
+            None => {
+                printf(
+                    b"%p is not reachable from roots\n\0".as_ptr().cast(),
+                    object,
+                );
+                return;
+            }
+        };
+        printf(
+            b"%s root at %p\n\0".as_ptr().cast(),
+            match retention.source {
+                RootSource::Stack => b"stack\0".as_ptr(),
+                RootSource::Callback => b"callback\0".as_ptr(),
+                RootSource::Static => b"static\0".as_ptr(),
+            },
+            retention.slot as *const u8,
+        );
+        for &object in retention.objects.iter() {
+            let rtti = (*object).vtable();
+            let name = symbol_name(rtti as *const u8).unwrap_or(b"<unknown>");
+            printf(
+                b"  -> %p (%.*s)\n\0".as_ptr().cast(),
+                object,
+                name.len() as i32,
+                name.as_ptr(),
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = retention;
+    }
+}
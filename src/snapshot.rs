@@ -0,0 +1,244 @@
+//! Heap snapshots for offline retention analysis.
+//!
+//! [immix_dump_heap] stops the world and writes graph of the heap to a text file:
+//!
+//! ```text
+//! IMMIXDUMP 1
+//! O <object> <size> <rtti> <name>
+//! E <from> <slot> <to>
+//! R <source> <slot> <object>
+//! ```
+//!
+//! - The first line is a header with format version.
+//! - `O` line describes allocated object: address of its header, size on the heap in bytes including header,
+//!   address of its RTTI and symbol name of RTTI or `?` if it is unknown. Objects allocated since the last GC cycle
+//!   are dumped too even if they are already unreachable.
+//! - `E` line describes reference stored at address `slot` in object `from`. References are found by running
+//!   `visit_references` of the object, for conservatively scanned objects every word that points to an object
+//!   is an edge too. Objects allocated without references have no edges.
+//! - `R` line describes root `object`. `source` is one of `stack` (thread stacks, TLS and registers), `callback`
+//!   (roots reported by [CollectRootsCallback](crate::CollectRootsCallback)) or `static` (static roots). GC has
+//!   no handle table of its own, VM handles are reported by the callback and are therefore labelled `callback`.
+//!   `slot` is address of the root or `0` for conservatively found roots.
+//!
+//! All numbers are hexadecimal without `0x` prefix. `O` lines come before `E` lines and `E` lines before `R` lines.
+//! Use [reader] to parse snapshot back.
+use crate::{collector, object::*, util::*, verify, Immix, StoppedWorld, SPACE};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+pub mod reader;
+
+/// Source of a root in a heap snapshot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(i32)]
+pub enum RootSource {
+    Stack = 0,
+    Callback,
+    Static,
+}
+
+impl RootSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RootSource::Stack => "stack",
+            RootSource::Callback => "callback",
+            RootSource::Static => "static",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "stack" => Some(RootSource::Stack),
+            "callback" => Some(RootSource::Callback),
+            "static" => Some(RootSource::Static),
+            _ => None,
+        }
+    }
+}
+
+impl Immix {
+    /// Collect roots of the heap without pinning them. Must only be called while the world is stopped.
+    pub(crate) unsafe fn snapshot_roots(
+        &mut self,
+        world: &StoppedWorld,
+    ) -> Vec<(RootSource, usize, *mut RawGc)> {
+        fn add(
+            result: &mut Vec<(RootSource, usize, *mut RawGc)>,
+            source: RootSource,
+            precise: Vec<*mut *mut RawGc>,
+            conservative: Vec<*mut RawGc>,
+        ) {
+            for slot in precise {
+                result.push((source, slot as usize, unsafe { *slot }));
+            }
+            for object in conservative {
+                result.push((source, 0, object));
+            }
+        }
+        let mut result = Vec::new();
+        let false_roots = self.false_roots.len();
+
+        let mut precise = Vec::new();
+        let mut conservative = Vec::new();
+        #[cfg(feature = "threaded")]
+        for thread in world.threads.iter() {
+            let thread = &**thread;
+            self.scan_stack(
+                thread.stack_bottom as *mut *mut u8,
+                thread.stack_end as *mut *mut u8,
+                &mut conservative,
+                &mut precise,
+            );
+            self.collect_roots(
+                thread.tls_begin as *mut *mut u8,
+                thread.tls_end as *mut *mut u8,
+                &mut conservative,
+            );
+        }
+        #[cfg(not(feature = "threaded"))]
+        {
+            let _ = world;
+            self.scan_stack(
+                self.stack_bottom as *mut *mut u8,
+                self.stack_end as *mut *mut u8,
+                &mut conservative,
+                &mut precise,
+            );
+        }
+        add(&mut result, RootSource::Stack, precise, conservative);
+
+        let mut children = verify::Children::new();
+        let mut cons = Vec::new();
+        for &(callback, data) in self.collect_roots_callback.iter() {
+            callback(
+                data,
+                children.tracer(),
+                ConservativeTracer {
+                    roots: &mut cons as *mut Vec<(usize, usize)> as *mut u8,
+                },
+            );
+        }
+        let mut conservative = Vec::new();
+        for &(begin, end) in cons.iter() {
+            self.collect_roots(
+                begin as *mut *mut u8,
+                end as *mut *mut u8,
+                &mut conservative,
+            );
+        }
+        // Values are taken from `Children` because tagged roots are stored with their tags.
+        for &(slot, object) in children.refs.iter() {
+            result.push((RootSource::Callback, slot, object));
+        }
+        add(&mut result, RootSource::Callback, Vec::new(), conservative);
+
+        let mut conservative = Vec::new();
+        for i in 0..self.static_roots.len() {
+            let (begin, end) = self.static_roots[i];
+            self.collect_roots(
+                begin as *mut *mut u8,
+                end as *mut *mut u8,
+                &mut conservative,
+            );
+        }
+        add(&mut result, RootSource::Static, Vec::new(), conservative);
+
+        self.false_roots.truncate(false_roots);
+        result
+    }
+
+    /// Return slots and values of references in `object`. Conservatively scanned objects also report every word
+    /// that points to an object.
+    pub(crate) unsafe fn object_edges(&self, object: *mut RawGc) -> Vec<(usize, *mut RawGc)> {
+        if collector::is_no_scan(&*self.immix, &self.mos, object) {
+            return Vec::new();
+        }
+        let mut edges = verify::children_of(object);
+        if (*object).rtti().conservative {
+            let tag_mask = crate::CONSERVATIVE_TAG_MASK.load(core::sync::atomic::Ordering::Relaxed);
+            let mut scan = object.cast::<usize>().add(1);
+            let end = object
+                .cast::<u8>()
+                .add((*object).object_size())
+                .cast::<usize>();
+            while scan < end {
+                let ptr = (scan.read_unaligned() & !tag_mask) as *mut u8;
+                if let Some(child) = collector::find_object(&*self.immix, &self.mos, &self.los, ptr)
+                {
+                    edges.push((scan as usize, child));
+                }
+                scan = scan.add(1);
+            }
+        }
+        edges
+    }
+
+    /// Write snapshot of the heap to `out`. Must only be called while the world is stopped.
+    pub(crate) unsafe fn write_snapshot(
+        &mut self,
+        world: &StoppedWorld,
+        out: &mut impl Write,
+    ) -> core::fmt::Result {
+        out.write_str("IMMIXDUMP 1\n")?;
+        let mut objects = Vec::new();
+        self.walk_heap(|info| objects.push(info));
+        for info in objects.iter() {
+            #[cfg(unix)]
+            let name = symbol_name(info.rtti as *const GCRTTI as *const u8)
+                .and_then(|name| core::str::from_utf8(name).ok())
+                .unwrap_or("?");
+            #[cfg(not(unix))]
+            let name = "?";
+            writeln!(
+                out,
+                "O {:x} {:x} {:x} {}",
+                info.object as usize, info.size, info.rtti as *const GCRTTI as usize, name
+            )?;
+        }
+        for info in objects.iter() {
+            for (slot, child) in self.object_edges(info.object) {
+                writeln!(
+                    out,
+                    "E {:x} {:x} {:x}",
+                    info.object as usize, slot, child as usize
+                )?;
+            }
+        }
+        for (source, slot, object) in self.snapshot_roots(world) {
+            writeln!(
+                out,
+                "R {} {:x} {:x}",
+                source.as_str(),
+                slot,
+                object as usize
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Stop the world and write snapshot of the heap to file at `path`. See [snapshot](self) for description of the
+/// format.
+///
+/// ## Return value
+/// Returns false if file could not be written.
+///
+/// # Safety
+/// `path` must point to a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn immix_dump_heap(path: *const libc::c_char) -> bool {
+    let mut out = match FileWriter::create(path) {
+        Some(out) => out,
+        None => return false,
+    };
+    crate::util::save_regs();
+    let world = crate::stop_the_world_retry();
+    #[cfg(not(feature = "threaded"))]
+    {
+        (*SPACE).stack_end = crate::stack_pointer() as *mut u8;
+    }
+    let result = (*SPACE).write_snapshot(&world, &mut out);
+    crate::resume_the_world(world);
+    out.finish() && result.is_ok()
+}
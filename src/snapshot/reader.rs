@@ -0,0 +1,126 @@
+//! Reader for heap snapshots written by [immix_dump_heap](super::immix_dump_heap).
+use super::RootSource;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Object described by `O` line.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SnapshotObject {
+    pub address: usize,
+    pub size: usize,
+    pub rtti: usize,
+    /// Symbol name of RTTI or `?`.
+    pub name: String,
+}
+
+/// Reference described by `E` line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SnapshotEdge {
+    pub from: usize,
+    pub slot: usize,
+    pub to: usize,
+}
+
+/// Root described by `R` line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SnapshotRoot {
+    pub source: RootSource,
+    /// Address of the root or 0 if root was found conservatively.
+    pub slot: usize,
+    pub object: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SnapshotError {
+    /// File could not be read.
+    Io,
+    /// Header is missing or has unsupported version.
+    Header,
+    /// Line with this number (starting from 1) is malformed.
+    Line(usize),
+}
+
+/// Heap snapshot. Objects are sorted by address.
+#[derive(Clone, Default, Debug)]
+pub struct HeapSnapshot {
+    pub objects: Vec<SnapshotObject>,
+    pub edges: Vec<SnapshotEdge>,
+    pub roots: Vec<SnapshotRoot>,
+}
+
+fn hex(field: Option<&str>) -> Option<usize> {
+    usize::from_str_radix(field?, 16).ok()
+}
+
+impl HeapSnapshot {
+    /// Parse snapshot from its text.
+    pub fn parse(text: &str) -> Result<Self, SnapshotError> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim_end) != Some("IMMIXDUMP 1") {
+            return Err(SnapshotError::Header);
+        }
+        let mut snapshot = Self::default();
+        for (ix, line) in lines.enumerate() {
+            let error = SnapshotError::Line(ix + 2);
+            let mut fields = line.splitn(5, ' ');
+            match fields.next() {
+                Some("O") => snapshot.objects.push(SnapshotObject {
+                    address: hex(fields.next()).ok_or(error)?,
+                    size: hex(fields.next()).ok_or(error)?,
+                    rtti: hex(fields.next()).ok_or(error)?,
+                    name: fields.next().ok_or(error)?.into(),
+                }),
+                Some("E") => snapshot.edges.push(SnapshotEdge {
+                    from: hex(fields.next()).ok_or(error)?,
+                    slot: hex(fields.next()).ok_or(error)?,
+                    to: hex(fields.next()).ok_or(error)?,
+                }),
+                Some("R") => snapshot.roots.push(SnapshotRoot {
+                    source: fields.next().and_then(RootSource::from_str).ok_or(error)?,
+                    slot: hex(fields.next()).ok_or(error)?,
+                    object: hex(fields.next()).ok_or(error)?,
+                }),
+                Some("") if line.trim().is_empty() => {}
+                _ => return Err(error),
+            }
+        }
+        snapshot.objects.sort_by_key(|object| object.address);
+        Ok(snapshot)
+    }
+
+    /// Read and parse snapshot from file at `path`.
+    pub fn read(path: &str) -> Result<Self, SnapshotError> {
+        let mut cpath = Vec::with_capacity(path.len() + 1);
+        cpath.extend_from_slice(path.as_bytes());
+        cpath.push(0);
+        let mut text = Vec::new();
+        unsafe {
+            let file = libc::fopen(cpath.as_ptr().cast(), b"rb\0".as_ptr().cast());
+            if file.is_null() {
+                return Err(SnapshotError::Io);
+            }
+            let mut buffer = [0u8; 4096];
+            loop {
+                let read = libc::fread(buffer.as_mut_ptr().cast(), 1, buffer.len(), file);
+                text.extend_from_slice(&buffer[..read]);
+                if read < buffer.len() {
+                    break;
+                }
+            }
+            let failed = libc::ferror(file) != 0;
+            libc::fclose(file);
+            if failed {
+                return Err(SnapshotError::Io);
+            }
+        }
+        Self::parse(core::str::from_utf8(&text).map_err(|_| SnapshotError::Io)?)
+    }
+
+    /// Find object with header at `address`.
+    pub fn object(&self, address: usize) -> Option<&SnapshotObject> {
+        self.objects
+            .binary_search_by_key(&address, |object| object.address)
+            .ok()
+            .map(|ix| &self.objects[ix])
+    }
+}
@@ -199,8 +199,8 @@ mod sync {
                 let index_start = Self::offset_to_index(offset_start);
                 let index_end = Self::offset_to_index(offset_end);
 
-                let bit_start = (offset_start / ALIGNMENT) * (size_of::<usize>() * 8);
-                let bit_end = (offset_end / ALIGNMENT) * (size_of::<usize>() * 8);
+                let bit_start = (offset_start / ALIGNMENT) % (size_of::<usize>() * 8);
+                let bit_end = (offset_end / ALIGNMENT) % (size_of::<usize>() * 8);
 
                 let mut left_edge = self
                     .bitmap_begin
@@ -487,6 +487,33 @@ mod unsync {
             (atomic_entry & mask) != 0
         }
 
+        pub fn visit_marked_range(
+            &self,
+            visit_begin: usize,
+            visit_end: usize,
+            mut visitor: impl FnMut(usize),
+        ) {
+            if visit_begin >= visit_end {
+                return;
+            }
+            unsafe {
+                let index_start = Self::offset_to_index(visit_begin - self.heap_begin);
+                let index_end = Self::offset_to_index(visit_end - 1 - self.heap_begin);
+                for i in index_start..=index_end {
+                    let mut w = *self.bitmap_begin.add(i);
+                    let ptr_base = Self::index_to_offset(i) as usize + self.heap_begin;
+                    while w != 0 {
+                        let shift = w.trailing_zeros() as usize;
+                        let obj = ptr_base + shift * ALIGNMENT;
+                        if obj >= visit_begin && obj < visit_end {
+                            visitor(obj);
+                        }
+                        w ^= 1 << shift;
+                    }
+                }
+            }
+        }
+
         #[inline]
         pub fn modify<const SET_BIT: bool>(&self, obj: usize) -> bool {
             unsafe {
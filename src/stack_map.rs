@@ -0,0 +1,239 @@
+//! Precise stack maps for frames emitted by a JIT compiler.
+//!
+//! A JIT registers the code ranges it owns and, for every safepoint inside of them, a stack map
+//! that describes which slots of the frame hold references into the GC heap. While a thread stack
+//! is scanned every word that is equal to a registered return address identifies a JIT frame:
+//! slots of that frame are reported as precise roots (so they are updated when objects are evacuated)
+//! and the frame itself is excluded from conservative scanning. Frames that are not described
+//! by a stack map are still scanned conservatively.
+//!
+//! ## Frame layout
+//! Slot offsets are relative to the *frame base* which is the address right above the slot that holds
+//! the return address, i.e. the stack pointer of the JIT frame right after the call returns:
+//!
+//! ```text
+//!    High |-------------------|
+//!         | JIT frame         |  <- frame base + frame_size
+//!         |   slot            |  <- frame base + offset
+//!         |-------------------|  <- frame base
+//!         | return address    |
+//!         |-------------------|
+//!         | callee frame      |
+//!    Low  |-------------------|
+//! ```
+use crate::object::RawGc;
+#[cfg(feature = "threaded")]
+use crate::util::locks::mutex::Mutex;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+#[cfg(feature = "threaded")]
+use parking_lot::lock_api::RawMutex;
+
+/// Stack map for a single safepoint.
+pub struct StackMap {
+    /// Size of the frame in bytes starting at frame base. This part of the stack is not scanned conservatively.
+    pub frame_size: usize,
+    /// Offsets of slots that hold GC references, relative to frame base.
+    pub slots: Vec<isize>,
+}
+
+/// Registry of JIT code ranges and their stack maps.
+pub struct StackMaps {
+    code_ranges: Vec<(usize, usize)>,
+    maps: BTreeMap<usize, StackMap>,
+    /// Lowest and highest registered code address, used to quickly reject stack words.
+    code_begin: usize,
+    code_end: usize,
+    #[cfg(feature = "threaded")]
+    lock: Mutex,
+}
+
+impl Default for StackMaps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StackMaps {
+    pub fn new() -> Self {
+        Self {
+            code_ranges: Vec::new(),
+            maps: BTreeMap::new(),
+            code_begin: usize::MAX,
+            code_end: 0,
+            #[cfg(feature = "threaded")]
+            lock: Mutex::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.maps.is_empty()
+    }
+
+    /// Register code range from `begin` to `end`. Stack maps can only be added for return addresses inside
+    /// of registered code ranges.
+    pub fn register_code_range(&mut self, begin: usize, end: usize) {
+        #[cfg(feature = "threaded")]
+        {
+            self.lock.lock();
+        }
+        let ix = self
+            .code_ranges
+            .binary_search(&(begin, end))
+            .unwrap_or_else(|x| x);
+        self.code_ranges.insert(ix, (begin, end));
+        self.update_bounds();
+        #[cfg(feature = "threaded")]
+        unsafe {
+            self.lock.unlock();
+        }
+    }
+
+    /// Unregister code range from `begin` to `end` and remove all stack maps that belong to it.
+    pub fn unregister_code_range(&mut self, begin: usize, end: usize) {
+        #[cfg(feature = "threaded")]
+        {
+            self.lock.lock();
+        }
+        self.code_ranges.retain(|&range| range != (begin, end));
+        let removed = self
+            .maps
+            .range(begin..end)
+            .map(|(&addr, _)| addr)
+            .collect::<Vec<_>>();
+        for addr in removed {
+            self.maps.remove(&addr);
+        }
+        self.update_bounds();
+        #[cfg(feature = "threaded")]
+        unsafe {
+            self.lock.unlock();
+        }
+    }
+
+    /// Add stack map for safepoint with `return_address`. Returns false if `return_address` is not inside of
+    /// registered code range.
+    pub fn add(&mut self, return_address: usize, map: StackMap) -> bool {
+        #[cfg(feature = "threaded")]
+        {
+            self.lock.lock();
+        }
+        let added = self.in_code_range(return_address);
+        if added {
+            self.maps.insert(return_address, map);
+        }
+        #[cfg(feature = "threaded")]
+        unsafe {
+            self.lock.unlock();
+        }
+        added
+    }
+
+    fn update_bounds(&mut self) {
+        self.code_begin = self
+            .code_ranges
+            .iter()
+            .map(|x| x.0)
+            .min()
+            .unwrap_or(usize::MAX);
+        self.code_end = self.code_ranges.iter().map(|x| x.1).max().unwrap_or(0);
+    }
+
+    fn in_code_range(&self, addr: usize) -> bool {
+        self.code_ranges
+            .iter()
+            .any(|&(begin, end)| begin <= addr && addr < end)
+    }
+
+    /// Find stack map for `return_address`.
+    #[inline]
+    pub fn lookup(&self, return_address: usize) -> Option<&StackMap> {
+        if return_address < self.code_begin || return_address >= self.code_end {
+            return None;
+        }
+        self.maps.get(&return_address)
+    }
+
+    /// Find JIT frames on stack between `from` and `to`.
+    ///
+    /// Slots of each frame are pushed to `precise_roots` and address ranges occupied by frames are pushed
+    /// to `frames` in ascending order.
+    ///
+    /// # Safety
+    /// `from` and `to` must be bounds of a stack of a stopped thread.
+    pub unsafe fn scan_frames(
+        &self,
+        from: *mut usize,
+        to: *mut usize,
+        precise_roots: &mut Vec<*mut *mut RawGc>,
+        frames: &mut Vec<(usize, usize)>,
+    ) {
+        let (mut scan, end) = if from < to { (from, to) } else { (to, from) };
+        while scan < end {
+            if let Some(map) = self.lookup(*scan) {
+                let base = scan.add(1) as usize;
+                for &offset in map.slots.iter() {
+                    let slot = (base as isize + offset) as *mut *mut RawGc;
+                    if !(*slot).is_null() {
+                        precise_roots.push(slot);
+                    }
+                }
+                debug!(
+                    "Found JIT frame at {:p} with return address 0x{:x}",
+                    base as *const u8, *scan
+                );
+                frames.push((base, base + map.frame_size));
+                scan = (base + map.frame_size) as *mut usize;
+                continue;
+            }
+            scan = scan.add(1);
+        }
+    }
+}
+
+/// Register code range from `begin` to `end` that contains JIT-compiled code with stack maps.
+#[no_mangle]
+pub extern "C" fn immix_register_code_range(begin: usize, end: usize) {
+    unsafe {
+        (*crate::SPACE).stack_maps.register_code_range(begin, end);
+    }
+}
+
+/// Unregister code range from `begin` to `end` and remove all of its stack maps. Must be invoked before
+/// JIT-compiled code is freed.
+#[no_mangle]
+pub extern "C" fn immix_unregister_code_range(begin: usize, end: usize) {
+    unsafe {
+        (*crate::SPACE).stack_maps.unregister_code_range(begin, end);
+    }
+}
+
+/// Add stack map for safepoint at `return_address`.
+///
+/// ## Inputs
+/// - `return_address`: Return address of the call at safepoint, must be inside of registered code range.
+/// - `frame_size`: Size of the frame in bytes starting at frame base (see module documentation).
+/// - `slots`: Offsets of slots that hold GC references relative to frame base. Slots might be null.
+/// - `count`: Number of offsets in `slots`.
+///
+/// ## Return value
+/// Returns false if `return_address` is not inside of registered code range.
+///
+/// # Safety
+/// `slots` must be valid for reads of `count` offsets.
+#[no_mangle]
+pub unsafe extern "C" fn immix_add_stack_map(
+    return_address: usize,
+    frame_size: usize,
+    slots: *const isize,
+    count: usize,
+) -> bool {
+    let slots = if count == 0 {
+        Vec::new()
+    } else {
+        core::slice::from_raw_parts(slots, count).to_vec()
+    };
+    (*crate::SPACE)
+        .stack_maps
+        .add(return_address, StackMap { frame_size, slots })
+}
@@ -0,0 +1,148 @@
+//! Conservative roots that live outside of thread stacks.
+//!
+//! By default GC never looks into static data segments (`.data`/`.bss`) or thread-local storage so `Gc` stored
+//! in a `static` or `#[thread_local]` is not visible to the collector. When static roots are enabled with
+//! [immix_enable_static_roots] writable segments of every loaded ELF object are registered at `immix_init` and
+//! static TLS block of each thread is registered at `immix_register_thread`.
+//!
+//! NOTE: Only Linux and Android are supported right now. TLS blocks of libraries loaded with `dlopen` are
+//! allocated dynamically and are not scanned.
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub(crate) static SCAN_STATIC_ROOTS: AtomicBool = AtomicBool::new(false);
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod elf {
+    use alloc::vec::Vec;
+    use libc::{c_int, c_void, dl_iterate_phdr, dl_phdr_info, size_t};
+    const PT_LOAD: u32 = 1;
+    const PT_TLS: u32 = 7;
+    const PF_W: u32 = 1 << 1;
+
+    unsafe extern "C" fn visit_segments(
+        info: *mut dl_phdr_info,
+        _size: size_t,
+        data: *mut c_void,
+    ) -> c_int {
+        let into = &mut *data.cast::<Vec<(usize, usize)>>();
+        let info = &*info;
+        for i in 0..info.dlpi_phnum as usize {
+            let phdr = &*info.dlpi_phdr.add(i);
+            if phdr.p_type == PT_LOAD && (phdr.p_flags & PF_W) != 0 {
+                let begin = info.dlpi_addr as usize + phdr.p_vaddr as usize;
+                into.push((begin, begin + phdr.p_memsz as usize));
+            }
+        }
+        0
+    }
+
+    unsafe extern "C" fn visit_tls(
+        info: *mut dl_phdr_info,
+        _size: size_t,
+        data: *mut c_void,
+    ) -> c_int {
+        let size = &mut *data.cast::<usize>();
+        let info = &*info;
+        for i in 0..info.dlpi_phnum as usize {
+            let phdr = &*info.dlpi_phdr.add(i);
+            if phdr.p_type == PT_TLS {
+                *size += crate::object::align_usize(phdr.p_memsz as usize, phdr.p_align as usize);
+            }
+        }
+        0
+    }
+
+    pub fn data_segments(into: &mut Vec<(usize, usize)>) {
+        unsafe {
+            dl_iterate_phdr(Some(visit_segments), into as *mut Vec<_> as *mut c_void);
+        }
+    }
+
+    pub fn static_tls_size() -> usize {
+        let mut size = 0usize;
+        unsafe {
+            dl_iterate_phdr(Some(visit_tls), &mut size as *mut usize as *mut c_void);
+        }
+        size
+    }
+}
+
+/// Push writable data segments of all loaded objects into `into`.
+pub(crate) fn data_segments(into: &mut Vec<(usize, usize)>) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        elf::data_segments(into);
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = into;
+    }
+}
+
+/// Return bounds of static TLS block of the current thread or `(0, 0)` if it is not known on this platform.
+pub(crate) fn thread_local_block() -> (usize, usize) {
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        target_arch = "x86_64"
+    ))]
+    unsafe {
+        // TLS variant II: static TLS blocks are placed right below the thread pointer.
+        let tp: usize;
+        asm!("mov {}, fs:0", out(reg) tp, options(nostack, readonly));
+        let size = elf::static_tls_size();
+        (tp - size, tp)
+    }
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        target_arch = "aarch64"
+    ))]
+    unsafe {
+        // TLS variant I: static TLS blocks follow 16 byte TCB.
+        let tp: usize;
+        asm!("mrs {}, tpidr_el0", out(reg) tp, options(nomem, nostack));
+        let size = elf::static_tls_size();
+        (tp + 16, tp + 16 + size)
+    }
+    #[cfg(not(all(
+        any(target_os = "linux", target_os = "android"),
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    {
+        (0, 0)
+    }
+}
+
+/// Enable scanning of static data segments and thread-local storage for conservative roots.
+///
+/// Should be invoked before `immix_init` and `immix_register_thread`. If GC is already initialized
+/// data segments and TLS block of the current thread are registered immediately.
+#[no_mangle]
+pub extern "C" fn immix_enable_static_roots() {
+    if SCAN_STATIC_ROOTS.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    unsafe {
+        if !crate::SPACE.is_null() {
+            (*crate::SPACE).register_static_roots();
+            crate::threading::register_thread_local_roots();
+        }
+    }
+}
+
+/// Add memory region from `begin` to `end` that is scanned for conservative roots at each GC cycle until
+/// it is removed with `immix_remove_roots`.
+#[no_mangle]
+pub extern "C" fn immix_add_roots(begin: usize, end: usize) {
+    unsafe {
+        (*crate::SPACE).add_roots(begin, end);
+    }
+}
+
+/// Remove all regions added by `immix_add_roots` that are entirely within `begin` and `end`.
+#[no_mangle]
+pub extern "C" fn immix_remove_roots(begin: usize, end: usize) {
+    unsafe {
+        (*crate::SPACE).remove_roots(begin, end);
+    }
+}
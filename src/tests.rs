@@ -1,6 +1,6 @@
 use crate::{
     immix_alloc, immix_alloc_safe, immix_collect, immix_init, immix_init_logger,
-    immix_noop_callback,
+    immix_noop_callback, make_rtti_for,
     object::*,
     threading::{immix_mutator_yieldpoint, immix_register_thread},
 };
@@ -34,12 +34,14 @@ fn inner_simple() {
     //println!("simple done");
 }
 
+#[cfg(not(feature = "strict-mutability"))]
 #[test]
 fn smash() {
     init();
     inner_smash();
 }
 
+#[cfg(not(feature = "strict-mutability"))]
 #[inline(never)]
 fn inner_smash() {
     let mut arr: [Option<Gc<i32>>; 7000] = [None; 7000];
@@ -70,6 +72,9 @@ static DUMMY_RTTI2048: GCRTTI = GCRTTI {
         s
     },
     finalizer: None,
+    conservative: false,
+    alignment: 0,
+    notify_relocation: false,
 };
 static DUMMY_RTTI4096: GCRTTI = GCRTTI {
     needs_finalization: false,
@@ -84,6 +89,9 @@ static DUMMY_RTTI4096: GCRTTI = GCRTTI {
         s
     },
     finalizer: None,
+    conservative: false,
+    alignment: 0,
+    notify_relocation: false,
 };
 #[test]
 fn middle() {
@@ -99,3 +107,1472 @@ fn middle() {
     }
     assert!(true);
 }
+
+#[test]
+fn stack_map_frames() {
+    use crate::stack_map::{StackMap, StackMaps};
+    let mut maps = StackMaps::new();
+    maps.register_code_range(0x1000, 0x2000);
+    assert!(maps.add(
+        0x1010,
+        StackMap {
+            frame_size: 3 * 8,
+            slots: vec![8, 16],
+        },
+    ));
+    assert!(!maps.add(
+        0x3000,
+        StackMap {
+            frame_size: 0,
+            slots: vec![],
+        }
+    ));
+    let obj = 0xdead0usize;
+    let mut stack: [usize; 8] = [0, 0x1010, 7, obj, 0, 1, 2, 3];
+    let from = stack.as_mut_ptr();
+    let to = unsafe { from.add(stack.len()) };
+    let mut precise = Vec::new();
+    let mut frames = Vec::new();
+    unsafe {
+        maps.scan_frames(from, to, &mut precise, &mut frames);
+    }
+    // null slot is skipped
+    assert_eq!(precise.len(), 1);
+    assert_eq!(precise[0] as usize, &stack[3] as *const usize as usize);
+    assert_eq!(frames, vec![(from as usize + 16, from as usize + 40)]);
+    maps.unregister_code_range(0x1000, 0x2000);
+    assert!(maps.is_empty());
+}
+
+static mut STATIC_ROOT: Option<Gc<i64>> = None;
+
+#[inline(never)]
+fn store_static_root() {
+    unsafe {
+        STATIC_ROOT = Some(immix_alloc_safe(42i64));
+    }
+}
+
+/// Overwrite dead stack frames so stale pointers are not found by conservative scanning.
+#[inline(never)]
+fn clobber_stack() {
+    let buf = [0usize; 4096];
+    std::hint::black_box(&buf);
+}
+
+/// Undo `immix_enable_static_roots` so tests that run later in the same process do not scan static data.
+fn disable_static_roots(saved: Vec<(usize, usize)>) {
+    crate::static_roots::SCAN_STATIC_ROOTS.store(false, core::sync::atomic::Ordering::Release);
+    unsafe {
+        STATIC_ROOT = None;
+        (*crate::SPACE).static_roots = saved;
+    }
+    #[cfg(feature = "threaded")]
+    {
+        let tls = crate::threading::immix_get_tls_state();
+        tls.tls_begin = core::ptr::null_mut();
+        tls.tls_end = core::ptr::null_mut();
+    }
+}
+
+#[test]
+fn static_roots() {
+    init();
+    let saved = unsafe { (*crate::SPACE).static_roots.clone() };
+    crate::static_roots::immix_enable_static_roots();
+    store_static_root();
+    clobber_stack();
+    immix_collect(false);
+    unsafe {
+        let root = STATIC_ROOT.unwrap();
+        let addr = crate::util::Address::from_ptr(root.ptr.as_ptr());
+        assert!((*(*crate::SPACE).immix).filter(addr).is_some());
+        assert_eq!(*root, 42);
+    }
+    disable_static_roots(saved);
+}
+
+/// Return bitwise complement of the false root so it does not stay on the stack as a false root itself.
+#[inline(never)]
+fn collect_with_false_root() -> usize {
+    let p = immix_alloc_safe(42);
+    // Points into a block that was not allocated yet.
+    let false_root =
+        std::hint::black_box(p.ptr.as_ptr() as usize + 16 * crate::constants::BLOCK_SIZE);
+    immix_collect(false);
+    !std::hint::black_box(false_root)
+}
+
+#[inline(never)]
+fn is_blacklisted(hidden: usize) -> bool {
+    let addr = !hidden;
+    unsafe {
+        (*(*(*crate::SPACE).immix).block_allocator)
+            .blacklist
+            .contains(addr)
+    }
+}
+
+#[test]
+fn blacklist() {
+    init();
+    let hidden = collect_with_false_root();
+    assert!(is_blacklisted(hidden));
+    assert!(crate::immix_blacklisted_bytes() >= crate::constants::LINE_SIZE);
+    clobber_stack();
+    for _ in 0..crate::constants::BLACKLIST_CYCLES {
+        immix_collect(false);
+    }
+    assert!(!is_blacklisted(hidden));
+}
+
+#[test]
+fn trace_tagged() {
+    use core::ptr::NonNull;
+    struct Forward(usize);
+    impl Tracer for Forward {
+        fn trace(&mut self, reference: &mut NonNull<RawGc>) {
+            assert_eq!(reference.as_ptr() as usize & 0x7, 0);
+            *reference = NonNull::new(self.0 as *mut RawGc).unwrap();
+        }
+    }
+    let mut tracer = Forward(0x2000);
+    let mut slot = 0x1000usize | 0x3;
+    tracer.trace_tagged(&mut slot, 0x7);
+    assert_eq!(slot, 0x2003);
+    // tagged null pointer is not traced
+    let mut slot = 0x3usize;
+    tracer.trace_tagged(&mut slot, 0x7);
+    assert_eq!(slot, 0x3);
+}
+
+static CONSERVATIVE_RTTI: GCRTTI = GCRTTI {
+    needs_finalization: false,
+    heap_size: {
+        extern "C" fn s(_: *mut u8) -> usize {
+            core::mem::size_of::<RawGc>() + 16
+        }
+        s
+    },
+    visit_references: crate::immix_noop_visit,
+    finalizer: None,
+    conservative: true,
+    alignment: 0,
+    notify_relocation: false,
+};
+
+#[inline(never)]
+fn alloc_conservative_holder() -> *mut RawGc {
+    let target = immix_alloc_safe(42i64);
+    let holder = immix_alloc(32, &CONSERVATIVE_RTTI).cast::<RawGc>();
+    unsafe {
+        let data = (*holder).data().cast::<usize>();
+        // pointer to object data
+        data.write((*target.ptr.as_ptr()).data() as usize);
+        data.add(1).write(0);
+    }
+    holder
+}
+
+#[test]
+fn conservative_heap_object() {
+    init();
+    let holder = std::hint::black_box(alloc_conservative_holder());
+    clobber_stack();
+    immix_collect(true);
+    immix_collect(true);
+    unsafe {
+        let target = (*holder).data().cast::<usize>().read() - core::mem::size_of::<RawGc>();
+        let addr = crate::util::Address::from(target);
+        assert!((*(*crate::SPACE).immix).filter(addr).is_some());
+        assert!(!(*(target as *mut RawGc)).is_pinned());
+        assert_eq!(*(*(target as *mut RawGc)).data().cast::<i64>(), 42);
+    }
+    std::hint::black_box(holder);
+}
+
+#[test]
+fn atomic_alloc() {
+    init();
+    let x = immix_alloc_safe(42u64);
+    let block = unsafe {
+        &*crate::block::ImmixBlock::get_block_ptr(crate::util::Address::from_ptr(x.ptr.as_ptr()))
+    };
+    assert!(block.no_scan);
+    // medium objects use overflow allocators, the one for objects without references has its own blocks
+    let bytes = crate::array::GcBytes::zeroed(1024);
+    let array = crate::array::GcArray::new(128, Some(x));
+    let block_of = |object: *mut RawGc| unsafe {
+        &*crate::block::ImmixBlock::get_block_ptr(crate::util::Address::from_ptr(object))
+    };
+    assert!(block_of(bytes.ptr.as_ptr()).no_scan);
+    assert!(!core::ptr::eq(block_of(bytes.ptr.as_ptr()), block));
+    assert!(!block_of(array.ptr.as_ptr()).no_scan);
+    immix_collect(true);
+    assert_eq!(*x, 42);
+    assert_eq!(bytes[1023], 0);
+    assert_eq!(*array[127].unwrap(), 42);
+    unsafe {
+        let addr = crate::util::Address::from_ptr(x.ptr.as_ptr());
+        assert!((*(*crate::SPACE).immix).filter(addr).is_some());
+    }
+}
+
+struct Node {
+    value: usize,
+    next: Option<Gc<Node>>,
+}
+
+impl HeapObject for Node {
+    const RTTI: GCRTTI = make_rtti_for!(Node);
+    fn visit_references(&mut self, tracer: &mut dyn Tracer) {
+        if let Some(next) = self.next.as_mut() {
+            tracer.trace(&mut next.ptr);
+        }
+    }
+}
+
+#[test]
+fn verification() {
+    init();
+    crate::verify::immix_enable_verification(true);
+    let mut list = immix_alloc_safe(Node {
+        value: 0,
+        next: None,
+    });
+    for value in 1..100 {
+        list = immix_alloc_safe(Node {
+            value,
+            next: Some(list),
+        });
+    }
+    immix_collect(true);
+    immix_collect(false);
+    crate::verify::immix_enable_verification(false);
+    let mut node = Some(list);
+    let mut expected = 100;
+    while let Some(n) = node {
+        expected -= 1;
+        assert_eq!(n.value, expected);
+        node = n.next;
+    }
+    assert_eq!(expected, 0);
+}
+
+#[test]
+fn walk_heap() {
+    init();
+    let node = immix_alloc_safe(Node {
+        value: 7,
+        next: None,
+    });
+    let large = immix_alloc(16 * 1024, &DUMMY_RTTI4096) as *mut RawGc;
+    let mut found_node = false;
+    let mut found_large = false;
+    for info in crate::heap_walk::heap_objects() {
+        if info.object == node.ptr.as_ptr() {
+            assert_eq!(
+                info.rtti as *const GCRTTI as usize,
+                object_ty_of_type::<Node>()
+            );
+            assert_eq!(info.size, 32);
+            found_node = true;
+        } else if info.object == large {
+            assert!(core::ptr::eq(info.rtti, &DUMMY_RTTI4096));
+            found_large = true;
+        }
+    }
+    assert!(found_node && found_large);
+
+    extern "C" fn count(data: *mut u8, _: *mut crate::GCObject, _: *const GCRTTI, _: usize) {
+        unsafe {
+            *data.cast::<usize>() += 1;
+        }
+    }
+    let mut objects = 0usize;
+    crate::heap_walk::immix_walk_heap(count, &mut objects as *mut usize as *mut u8);
+    assert!(objects >= 2);
+    assert_eq!(node.value, 7);
+}
+
+#[test]
+fn heap_snapshot() {
+    use crate::snapshot::{reader::HeapSnapshot, RootSource};
+    init();
+    let tail = immix_alloc_safe(Node {
+        value: 1,
+        next: None,
+    });
+    let head = immix_alloc_safe(Node {
+        value: 2,
+        next: Some(tail),
+    });
+    let path = format!("/tmp/immix-snapshot-{}", std::process::id());
+    assert!(unsafe { crate::snapshot::immix_dump_heap(format!("{}\0", path).as_ptr().cast()) });
+    let snapshot = HeapSnapshot::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let head_addr = head.ptr.as_ptr() as usize;
+    let tail_addr = tail.ptr.as_ptr() as usize;
+    let object = snapshot.object(head_addr).unwrap();
+    assert_eq!(object.size, 32);
+    assert_eq!(object.rtti, object_ty_of_type::<Node>());
+    assert!(snapshot.object(tail_addr).is_some());
+    assert!(snapshot
+        .edges
+        .iter()
+        .any(|edge| edge.from == head_addr && edge.to == tail_addr));
+    assert!(!snapshot.edges.iter().any(|edge| edge.from == tail_addr));
+    assert!(snapshot
+        .roots
+        .iter()
+        .any(|root| root.source == RootSource::Stack && root.object == head_addr));
+    assert_eq!(head.next.unwrap().value, 1);
+
+    assert_eq!(
+        HeapSnapshot::parse("IMMIXDUMP 1\nO 10 20 30 foo\nX\n").unwrap_err(),
+        crate::snapshot::reader::SnapshotError::Line(3)
+    );
+}
+
+/// Allocate list of 5 nodes and return its head and hidden address of its last node.
+#[inline(never)]
+fn alloc_retained_list() -> (Gc<Node>, usize) {
+    let mut list = immix_alloc_safe(Node {
+        value: 0,
+        next: None,
+    });
+    let target = list.ptr.as_ptr() as usize ^ usize::MAX;
+    for value in 1..5 {
+        list = immix_alloc_safe(Node {
+            value,
+            next: Some(list),
+        });
+    }
+    (list, target)
+}
+
+#[test]
+fn retention_path() {
+    use crate::{retention::find_retention_path, snapshot::RootSource};
+    init();
+    let (list, target) = alloc_retained_list();
+    clobber_stack();
+    let target = (target ^ usize::MAX) as *mut RawGc;
+    let path = find_retention_path(target).unwrap();
+    assert_eq!(path.source, RootSource::Stack);
+    assert_eq!(path.objects.len(), 5);
+    assert_eq!(path.objects[0], list.ptr.as_ptr());
+    assert_eq!(*path.objects.last().unwrap(), target);
+    // marks are not changed by the query
+    assert_eq!(unsafe { (*target).get_mark() }, unsafe {
+        (*list.ptr.as_ptr()).get_mark()
+    });
+    assert_eq!(list.value, 4);
+}
+
+#[test]
+fn retention_path_of_stack_referenced_object() {
+    use crate::{retention::find_retention_path, snapshot::RootSource};
+    init();
+    let node = immix_alloc_safe(Node {
+        value: 1,
+        next: None,
+    });
+    let path = find_retention_path(node.ptr.as_ptr()).unwrap();
+    assert_eq!(path.source, RootSource::Stack);
+    assert_eq!(path.objects, vec![node.ptr.as_ptr()]);
+    assert_eq!(node.value, 1);
+}
+
+#[test]
+fn allocation_sampling() {
+    use crate::profiler::*;
+    init();
+    immix_set_sampling_interval(1024);
+    for value in 0..100 {
+        immix_alloc_safe(Node { value, next: None });
+    }
+    immix_set_sampling_interval(0);
+    let samples = allocation_samples()
+        .into_iter()
+        .filter(|sample| sample.rtti == object_ty_of_type::<Node>())
+        .collect::<Vec<_>>();
+    assert!(samples.len() >= 3);
+    for sample in samples.iter() {
+        assert_eq!(sample.size, 32);
+        assert_eq!(sample.weight % 1024, 0);
+        assert!(!sample.frames.is_empty());
+    }
+    let weight = samples.iter().map(|sample| sample.weight).sum::<usize>();
+    let folded = folded_stacks(&samples);
+    let folded_weight = folded
+        .lines()
+        .map(|line| line.rsplit(' ').next().unwrap().parse::<usize>().unwrap())
+        .sum::<usize>();
+    assert_eq!(folded_weight, weight);
+    let profile = pprof_profile(&samples, 1024);
+    // first field is `sample_type` message
+    assert_eq!(profile[0], 0x0a);
+}
+
+#[derive(libimmixcons_derive::HeapObject)]
+struct Pair<T> {
+    left: T,
+    right: Option<Gc<Node>>,
+    #[gc(skip)]
+    tag: usize,
+}
+
+#[derive(libimmixcons_derive::HeapObject)]
+enum Tree {
+    Leaf(i64),
+    Branch { left: Gc<Tree>, right: Gc<Tree> },
+    Empty,
+}
+
+#[derive(libimmixcons_derive::HeapObject)]
+#[gc(finalize)]
+struct Finalized {
+    items: Vec<Gc<Tree>>,
+}
+
+#[test]
+fn derive_heap_object() {
+    init();
+    let node = immix_alloc_safe(Node {
+        value: 1,
+        next: None,
+    });
+    let pair = immix_alloc_safe(Pair {
+        left: node,
+        right: Some(node),
+        tag: node.ptr.as_ptr() as usize,
+    });
+    let children = unsafe { crate::verify::children_of(pair.ptr.as_ptr()) };
+    assert_eq!(children.len(), 2);
+    assert!(children
+        .iter()
+        .all(|&(_, child)| child == node.ptr.as_ptr()));
+
+    let left = immix_alloc_safe(Tree::Leaf(1));
+    let right = immix_alloc_safe(Tree::Empty);
+    let branch = immix_alloc_safe(Tree::Branch { left, right });
+    let children = unsafe { crate::verify::children_of(branch.ptr.as_ptr()) };
+    assert_eq!(children.len(), 2);
+    assert_eq!(children[0].1, left.ptr.as_ptr());
+    assert_eq!(children[1].1, right.ptr.as_ptr());
+    assert!(unsafe { crate::verify::children_of(left.ptr.as_ptr()) }.is_empty());
+
+    assert!(Finalized::RTTI.needs_finalization);
+    assert!(!<Pair<Gc<Node>>>::RTTI.needs_finalization);
+    let finalized = immix_alloc_safe(Finalized {
+        items: vec![branch, left],
+    });
+    assert_eq!(
+        unsafe { crate::verify::children_of(finalized.ptr.as_ptr()) }.len(),
+        2
+    );
+    immix_collect(true);
+    match *branch {
+        Tree::Branch { left, .. } => match *left {
+            Tree::Leaf(value) => assert_eq!(value, 1),
+            _ => panic!("expected leaf"),
+        },
+        _ => panic!("expected branch"),
+    }
+    assert_eq!(pair.left.value, 1);
+    assert_eq!(pair.tag, node.ptr.as_ptr() as usize);
+    assert_eq!(finalized.items.len(), 2);
+}
+
+#[test]
+fn container_heap_objects() {
+    init();
+    let node = immix_alloc_safe(Node {
+        value: 7,
+        next: None,
+    });
+    let option = immix_alloc_safe(Some(node));
+    let tuple = immix_alloc_safe((1u32, node, [node; 2]));
+    let gc = immix_alloc_safe(node);
+    assert!(!<Option<Gc<Node>>>::RTTI.needs_finalization);
+    for object in [option.ptr.as_ptr(), tuple.ptr.as_ptr(), gc.ptr.as_ptr()].iter() {
+        let children = unsafe { crate::verify::children_of(*object) };
+        assert!(!children.is_empty());
+        assert!(children
+            .iter()
+            .all(|&(_, child)| child == node.ptr.as_ptr()));
+    }
+    assert_eq!(
+        unsafe { crate::verify::children_of(tuple.ptr.as_ptr()) }.len(),
+        3
+    );
+
+    #[cfg(feature = "alloc")]
+    {
+        use std::collections::{BTreeMap, VecDeque};
+        assert!(<Vec<Gc<Node>>>::RTTI.needs_finalization);
+        let vec = immix_alloc_safe(vec![node, node]);
+        let boxed = immix_alloc_safe(Box::new(Some(node)));
+        let deque = immix_alloc_safe(vec![node].into_iter().collect::<VecDeque<_>>());
+        let mut map = BTreeMap::new();
+        map.insert(1, node);
+        map.insert(2, node);
+        let map = immix_alloc_safe(map);
+        let string = immix_alloc_safe(String::from("immix"));
+        for &(object, count) in [
+            (vec.ptr.as_ptr(), 2),
+            (boxed.ptr.as_ptr(), 1),
+            (deque.ptr.as_ptr(), 1),
+            (map.ptr.as_ptr(), 2),
+        ]
+        .iter()
+        {
+            let children = unsafe { crate::verify::children_of(object) };
+            assert_eq!(children.len(), count);
+            assert!(children
+                .iter()
+                .all(|&(_, child)| child == node.ptr.as_ptr()));
+        }
+        immix_collect(true);
+        assert_eq!(vec[1].value, 7);
+        assert_eq!(map[&2].value, 7);
+        assert_eq!(&**string, "immix");
+    }
+    #[cfg(feature = "std")]
+    {
+        let mut map = std::collections::HashMap::new();
+        map.insert("node", node);
+        let map = immix_alloc_safe(map);
+        assert_eq!(
+            unsafe { crate::verify::children_of(map.ptr.as_ptr()) }.len(),
+            1
+        );
+    }
+    immix_collect(true);
+    assert_eq!(option.unwrap().value, 7);
+    assert_eq!(tuple.1.value, 7);
+}
+
+#[test]
+fn large_object_space_lookup() {
+    use crate::large_object_space::{LargeObjectSpace, PreciseAllocation};
+    use crate::util::Address;
+    let mut los = LargeObjectSpace::new(8 * 1024 * 1024);
+    let rtti = &DUMMY_RTTI2048 as *const GCRTTI as usize;
+    let objects: Vec<Address> = (0..16)
+        .map(|i| los.alloc(16 * 1024 + i * 1000, rtti, false, 0))
+        .collect();
+    for &object in objects.iter() {
+        assert!(!object.is_null());
+        assert!(los.contains(object));
+        assert!(!los.contains(object.offset(16)));
+        assert_eq!(
+            los.find_interior(object.offset(10000)),
+            Some(object.to_mut_ptr::<RawGc>())
+        );
+    }
+    assert!(!los.contains(Address::from(0x1000)));
+    let huge = los.alloc(4 * 1024 * 1024, rtti, false, 0);
+    assert!(!huge.is_null());
+    assert!(los.contains(huge));
+    assert_eq!(
+        los.find_interior(huge.offset(3 * 1024 * 1024)),
+        Some(huge.to_mut_ptr::<RawGc>())
+    );
+    // reservation is exhausted
+    assert!(los.alloc(4 * 1024 * 1024, rtti, false, 0).is_null());
+    let live: usize = (0..16).map(|i| 16 * 1024 + i * 1000).sum::<usize>() + 4 * 1024 * 1024;
+    assert_eq!(los.live_bytes(), live);
+    // nothing is marked so every allocation is freed and pages are reused
+    assert_eq!(los.sweep(!los.current_live_mark), live);
+    assert_eq!(los.live_bytes(), 0);
+    assert!(los.allocations.is_empty());
+    assert!(!los.contains(objects[3]));
+    assert!(!los.contains(huge));
+    assert_eq!(los.find_interior(objects[3].offset(16)), None);
+    assert_eq!(los.alloc(16 * 1024, rtti, false, 0), objects[0]);
+    let aligned = los.alloc(16 * 1024, rtti, false, 4096);
+    assert_eq!(
+        (aligned.to_usize() + PreciseAllocation::ALIGNED_VALUE_OFFSET) % 4096,
+        0
+    );
+}
+
+#[test]
+fn medium_object_space_lookup() {
+    use crate::medium_object_space::{MediumObjectSpace, CHUNK_SIZE};
+    use crate::util::Address;
+    let mut mos = MediumObjectSpace::new(2 * CHUNK_SIZE);
+    let rtti = &DUMMY_RTTI2048 as *const GCRTTI as usize;
+    let alloc = |mos: &mut MediumObjectSpace, size: usize| {
+        let object = mos.alloc(size, false);
+        if !object.is_null() {
+            unsafe { object.to_mut_ptr::<RawGc>().write(RawGc::new(rtti)) };
+        }
+        object
+    };
+    // objects of one size class share a chunk
+    let objects: Vec<Address> = (0..4).map(|_| alloc(&mut mos, 12 * 1024)).collect();
+    for (ix, &object) in objects.iter().enumerate() {
+        assert_eq!(object.to_usize(), objects[0].to_usize() + ix * 12 * 1024);
+        assert!(mos.contains(object));
+        assert!(!mos.contains(object.offset(16)));
+        assert_eq!(
+            mos.find_interior(object.offset(10000)),
+            Some(object.to_mut_ptr::<RawGc>())
+        );
+    }
+    assert_eq!(mos.find_interior(objects[3].offset(12 * 1024)), None);
+    let huge = alloc(&mut mos, 256 * 1024);
+    assert_eq!(huge.to_usize(), objects[0].to_usize() + CHUNK_SIZE);
+    // region is exhausted
+    assert!(alloc(&mut mos, 100 * 1024).is_null());
+    mos.mark(objects[1]);
+    mos.mark(huge);
+    assert_eq!(mos.sweep(), 3 * 12 * 1024);
+    assert!(mos.contains(objects[1]));
+    assert!(!mos.contains(objects[0]));
+    // free cells are reused lowest address first
+    assert_eq!(alloc(&mut mos, 12 * 1024), objects[0]);
+    // nothing is marked so every chunk is freed and can be used for another size class
+    assert_eq!(mos.sweep(), 2 * 12 * 1024 + 256 * 1024);
+    assert_eq!(mos.committed_bytes(), 0);
+    assert!(!alloc(&mut mos, 100 * 1024).is_null());
+}
+
+fn in_medium_object_space(object: *mut RawGc) -> bool {
+    unsafe {
+        (*crate::SPACE)
+            .mos
+            .contains(crate::util::Address::from_ptr(object))
+    }
+}
+
+fn in_large_object_space(object: *mut RawGc) -> bool {
+    unsafe {
+        (*crate::SPACE)
+            .los
+            .contains(crate::util::Address::from_ptr(object))
+    }
+}
+
+#[inline(never)]
+fn large_array_in_los(node: Gc<Node>) -> bool {
+    let large =
+        crate::array::GcArray::from_slice(&[node; crate::constants::MEDIUM_OBJECT_LIMIT / 8]);
+    large.iter().all(|child| child.value == 3) && in_large_object_space(large.ptr.as_ptr())
+}
+
+#[test]
+fn variable_length_objects() {
+    use crate::array::{GcArray, GcBytes, GcStr};
+    init();
+    let node = immix_alloc_safe(Node {
+        value: 3,
+        next: None,
+    });
+    let mut array = GcArray::new(4, Some(node));
+    unsafe { array.get_mut_unchecked()[3] = None };
+    assert_eq!(array.len(), 4);
+    assert_eq!(array[1..3].len(), 2);
+    assert_eq!(
+        unsafe { (*array.ptr.as_ptr()).object_size() },
+        align_usize(core::mem::size_of::<RawGc>() + 8 + 4 * 8, 16)
+    );
+    let children = unsafe { crate::verify::children_of(array.ptr.as_ptr()) };
+    assert_eq!(children.len(), 3);
+
+    let mut bytes = GcBytes::zeroed(5);
+    unsafe { bytes.get_mut_unchecked()[0] = 42 };
+    assert_eq!(&bytes[..2], &[42, 0]);
+    let string = GcStr::new("hello, immix");
+    assert_eq!(&string[..5], "hello");
+    assert_eq!(string.len(), 12);
+
+    let small = GcBytes::from_slice(b"abc");
+    assert!(!in_large_object_space(small.ptr.as_ptr()));
+
+    immix_collect(true);
+    assert_eq!(array[0].unwrap().value, 3);
+    assert!(array[3].is_none());
+    assert_eq!(bytes[0], 42);
+    assert_eq!(string.as_str(), "hello, immix");
+    assert_eq!(&small[..], b"abc");
+    assert!(large_array_in_los(node));
+}
+
+static CLONES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+static DROPS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Element whose third clone panics.
+struct PanicOnClone;
+
+impl Clone for PanicOnClone {
+    fn clone(&self) -> Self {
+        if CLONES.fetch_add(1, std::sync::atomic::Ordering::Relaxed) == 2 {
+            panic!("clone failed");
+        }
+        PanicOnClone
+    }
+}
+
+impl Drop for PanicOnClone {
+    fn drop(&mut self) {
+        DROPS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+impl Trace for PanicOnClone {
+    fn trace(&mut self, _tracer: &mut dyn Tracer) {}
+}
+
+#[inline(never)]
+fn alloc_array_with_panicking_clone() {
+    let result = std::panic::catch_unwind(|| crate::array::GcArray::new(4, PanicOnClone));
+    assert!(result.is_err());
+}
+
+#[test]
+fn array_clone_panic_drops_only_initialized_elements() {
+    use std::sync::atomic::Ordering;
+    init();
+    alloc_array_with_panicking_clone();
+    // the value passed to `GcArray::new` is dropped while unwinding
+    assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+    clobber_stack();
+    immix_collect(false);
+    assert_eq!(DROPS.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+#[should_panic(expected = "overflows usize")]
+fn array_size_overflow() {
+    init();
+    crate::array::GcArray::new(usize::MAX / 4, 0u64);
+}
+
+#[derive(libimmixcons_derive::HeapObject)]
+struct LargeHolder {
+    array: Gc<crate::array::GcArray<Option<Gc<Node>>>>,
+}
+
+/// Allocate small object that holds the only reference to large array of references to one node.
+#[inline(never)]
+fn alloc_large_holder() -> Gc<LargeHolder> {
+    let node = immix_alloc_safe(Node {
+        value: 5,
+        next: None,
+    });
+    let array = crate::array::GcArray::new(crate::constants::MEDIUM_OBJECT_LIMIT / 8, Some(node));
+    immix_alloc_safe(LargeHolder { array })
+}
+
+#[test]
+fn large_object_reachable_from_heap() {
+    init();
+    let holder = alloc_large_holder();
+    clobber_stack();
+    // mark bit flips every cycle, the array must survive each of them
+    for cycle in 0..4 {
+        immix_collect(cycle % 2 == 0);
+        let array = holder.array;
+        assert!(in_large_object_space(array.ptr.as_ptr()));
+        assert!(array.iter().all(|node| node.unwrap().value == 5));
+    }
+}
+
+/// Allocate large byte array that is not referenced and return its hidden address.
+#[inline(never)]
+fn alloc_dead_large_array() -> usize {
+    let bytes = crate::array::GcBytes::zeroed(crate::constants::MEDIUM_OBJECT_LIMIT);
+    bytes.ptr.as_ptr() as usize ^ usize::MAX
+}
+
+#[test]
+fn unreachable_large_object_is_freed() {
+    init();
+    let holder = alloc_large_holder();
+    let freed = crate::immix_large_object_freed_bytes();
+    let hidden = alloc_dead_large_array();
+    clobber_stack();
+    immix_collect(false);
+    assert!(!in_large_object_space((hidden ^ usize::MAX) as *mut RawGc));
+    assert!(
+        crate::immix_large_object_freed_bytes() >= freed + crate::constants::MEDIUM_OBJECT_LIMIT
+    );
+    assert!(in_large_object_space(holder.array.ptr.as_ptr()));
+}
+
+#[test]
+fn large_allocation_triggers_collection() {
+    init();
+    let freed = crate::immix_large_object_freed_bytes();
+    unsafe {
+        let space = &mut *crate::SPACE;
+        space.large_threshold = space.los.live_bytes() + crate::constants::MEDIUM_OBJECT_LIMIT;
+    }
+    // live large bytes go over the threshold, the next large allocation collects
+    alloc_dead_large_array();
+    clobber_stack();
+    assert_eq!(crate::immix_large_object_freed_bytes(), freed);
+    alloc_dead_large_array();
+    assert!(
+        crate::immix_large_object_freed_bytes() >= freed + crate::constants::MEDIUM_OBJECT_LIMIT
+    );
+}
+
+/// Allocate small object that holds the only reference to medium array and medium byte array that is not
+/// referenced. Returns the holder and hidden address of the byte array.
+#[inline(never)]
+fn alloc_medium_objects() -> (Gc<LargeHolder>, usize) {
+    let node = immix_alloc_safe(Node {
+        value: 5,
+        next: None,
+    });
+    let array = crate::array::GcArray::new(64 * 1024 / 8, Some(node));
+    let dead = crate::array::GcBytes::zeroed(20 * 1024);
+    (
+        immix_alloc_safe(LargeHolder { array }),
+        dead.ptr.as_ptr() as usize ^ usize::MAX,
+    )
+}
+
+#[test]
+fn medium_objects() {
+    use crate::array::GcBytes;
+    use crate::constants::MEDIUM_OBJECT_LIMIT;
+    init();
+    let (holder, hidden) = alloc_medium_objects();
+    assert!(in_medium_object_space(holder.array.ptr.as_ptr()));
+    assert!(in_medium_object_space((hidden ^ usize::MAX) as *mut RawGc));
+    let freed = crate::immix_medium_object_freed_bytes();
+    clobber_stack();
+    for cycle in 0..4 {
+        immix_collect(cycle % 2 == 0);
+        let array = holder.array;
+        assert!(in_medium_object_space(array.ptr.as_ptr()));
+        assert!(array.iter().all(|node| node.unwrap().value == 5));
+    }
+    assert!(!in_medium_object_space((hidden ^ usize::MAX) as *mut RawGc));
+    assert!(crate::immix_medium_object_freed_bytes() >= freed + 20 * 1024);
+    // objects above the limit go to large object space
+    let medium = GcBytes::zeroed(MEDIUM_OBJECT_LIMIT - 64);
+    assert!(in_medium_object_space(medium.ptr.as_ptr()));
+    let huge = GcBytes::zeroed(MEDIUM_OBJECT_LIMIT);
+    assert!(in_large_object_space(huge.ptr.as_ptr()));
+}
+
+#[test]
+fn exhausted_medium_object_space_falls_back_to_large_object_space() {
+    use crate::array::GcBytes;
+    use crate::medium_object_space::{MediumObjectSpace, CHUNK_SIZE};
+    init();
+    let space = unsafe { &mut *crate::SPACE };
+    // one chunk holds two objects of the largest size class
+    let mos = core::mem::replace(&mut space.mos, MediumObjectSpace::new(CHUNK_SIZE));
+    let first = GcBytes::zeroed(200 * 1024);
+    let second = GcBytes::zeroed(200 * 1024);
+    let third = GcBytes::zeroed(200 * 1024);
+    assert!(in_medium_object_space(first.ptr.as_ptr()));
+    assert!(in_medium_object_space(second.ptr.as_ptr()));
+    assert!(in_large_object_space(third.ptr.as_ptr()));
+    assert_eq!(third.len(), 200 * 1024);
+    // no more emergency collections until the next regular one, live mark flips on every collection
+    assert!(space.medium_exhausted);
+    let live_mark = space.current_live_mark;
+    let fourth = GcBytes::zeroed(200 * 1024);
+    assert!(in_large_object_space(fourth.ptr.as_ptr()));
+    assert_eq!(space.current_live_mark, live_mark);
+    immix_collect(false);
+    assert!(!space.medium_exhausted);
+    space.mos = mos;
+}
+
+#[derive(libimmixcons_derive::HeapObject)]
+struct Cells {
+    count: crate::cell::GcCell<usize>,
+    next: crate::cell::GcCell<Option<Gc<Node>>>,
+    items: crate::cell::GcRefCell<[Option<Gc<Node>>; 2]>,
+}
+
+#[test]
+fn gc_cells() {
+    use crate::cell::{GcCell, GcRefCell};
+    init();
+    let node = immix_alloc_safe(Node {
+        value: 5,
+        next: None,
+    });
+    let cells = immix_alloc_safe(Cells {
+        count: GcCell::new(0),
+        next: GcCell::new(None),
+        items: GcRefCell::new([None, None]),
+    });
+    cells.count.set(1);
+    assert_eq!(cells.count.replace(2), 1);
+    cells.next.set(Some(node));
+    cells.items.borrow_mut()[1] = Some(node);
+    {
+        let items = cells.items.borrow();
+        assert!(cells.items.try_borrow_mut().is_err());
+        assert!(cells.items.try_borrow().is_ok());
+        assert!(items[0].is_none());
+    }
+    assert!(cells.items.try_borrow_mut().is_ok());
+    let children = unsafe { crate::verify::children_of(cells.ptr.as_ptr()) };
+    assert_eq!(children.len(), 2);
+    immix_collect(true);
+    assert_eq!(cells.count.get(), 2);
+    assert_eq!(cells.next.get().unwrap().value, 5);
+    assert_eq!(cells.items.borrow()[1].unwrap().value, 5);
+}
+
+#[cfg(feature = "strict-mutability")]
+#[derive(libimmixcons_derive::HeapObject)]
+struct Counter {
+    value: crate::cell::GcCell<i32>,
+}
+
+/// Like `smash` but objects are mutated through cells since `Gc` does not implement `DerefMut`.
+#[cfg(feature = "strict-mutability")]
+#[test]
+fn smash_strict_mutability() {
+    use crate::cell::GcCell;
+    init();
+    let mut arr: [Option<Gc<Counter>>; 7000] = [None; 7000];
+    for i in 0..7000 {
+        arr[i] = Some(immix_alloc_safe(Counter {
+            value: GcCell::new(4),
+        }));
+        immix_mutator_yieldpoint();
+        if i % 3000 == 0 {
+            immix_collect(true);
+        }
+        if i % 5678 == 0 {
+            assert_eq!(arr[i / 2000].unwrap().value.get(), 4);
+            arr[i / 2000].unwrap().value.set(42);
+        }
+    }
+    assert_eq!(arr[0].unwrap().value.get(), 42);
+}
+
+trait Callable: Trace {
+    fn call(&self, arg: usize) -> usize;
+}
+
+crate::impl_heap_object_for_dyn!(dyn Callable);
+
+#[derive(libimmixcons_derive::HeapObject)]
+struct Adder {
+    base: Gc<Node>,
+}
+
+impl Callable for Adder {
+    fn call(&self, arg: usize) -> usize {
+        self.base.value + arg
+    }
+}
+
+#[test]
+fn dynamically_sized_objects() {
+    use crate::{immix_alloc_slice, immix_alloc_str, immix_alloc_unsize};
+    init();
+    let node = immix_alloc_safe(Node {
+        value: 10,
+        next: None,
+    });
+    let slice = immix_alloc_slice(&[Some(node), None, Some(node)]);
+    assert_eq!(slice.len(), 3);
+    assert!(slice[1].is_none());
+    assert_eq!(
+        unsafe { (*slice.ptr.as_ptr()).object_size() },
+        align_usize(core::mem::size_of::<RawGc>() + 8 + 3 * 8, 16)
+    );
+    assert_eq!(
+        unsafe { crate::verify::children_of(slice.ptr.as_ptr()) }.len(),
+        2
+    );
+    let from_array: Gc<[u32]> = immix_alloc_unsize([1u32, 2, 3, 4]);
+    assert_eq!(&from_array[1..], &[2, 3, 4]);
+    let string = immix_alloc_str("dynamically sized");
+    assert_eq!(&string[..11], "dynamically");
+    assert_eq!(Gc::from_raw(&*string as *const str).ptr, string.ptr);
+
+    let callable: Gc<dyn Callable> = immix_alloc_unsize(Adder { base: node });
+    assert_eq!(callable.call(1), 11);
+    let children = unsafe { crate::verify::children_of(callable.ptr.as_ptr()) };
+    assert_eq!(children.len(), 1);
+    assert_eq!(children[0].1, node.ptr.as_ptr());
+
+    immix_collect(true);
+    assert_eq!(slice[2].unwrap().value, 10);
+    assert_eq!(from_array.iter().sum::<u32>(), 10);
+    assert_eq!(&*string, "dynamically sized");
+    assert_eq!(callable.call(5), 15);
+}
+
+#[test]
+#[should_panic(expected = "alignment above word size")]
+fn dynamically_sized_over_aligned_value() {
+    init();
+    crate::immix_alloc_slice(&[Wide(1), Wide(2)]);
+}
+
+#[derive(Clone, libimmixcons_derive::HeapObject)]
+#[repr(align(32))]
+struct Wide(u64);
+
+#[derive(libimmixcons_derive::HeapObject)]
+#[repr(align(64))]
+struct Vector {
+    lanes: [f32; 16],
+    next: Option<Gc<Node>>,
+}
+
+#[derive(libimmixcons_derive::HeapObject)]
+#[repr(align(128))]
+struct LargeVector {
+    lanes: [f32; 4096],
+}
+
+#[inline(never)]
+fn large_vector_aligned() -> bool {
+    let vector = immix_alloc_safe(LargeVector { lanes: [1.0; 4096] });
+    &*vector as *const LargeVector as usize % 128 == 0
+        && Gc::from_raw(&*vector as *const LargeVector).ptr == vector.ptr
+        && vector.lanes[4095] == 1.0
+}
+
+#[test]
+fn over_aligned_objects() {
+    init();
+    let node = immix_alloc_safe(Node {
+        value: 4,
+        next: None,
+    });
+    let mut vectors = Vec::new();
+    for i in 0..64 {
+        let _small = immix_alloc_safe(i as u32);
+        let vector = immix_alloc_safe(Vector {
+            lanes: [i as f32; 16],
+            next: Some(node),
+        });
+        assert_eq!(&*vector as *const Vector as usize % 64, 0);
+        assert_eq!(Gc::from_raw(&*vector as *const Vector).ptr, vector.ptr);
+        vectors.push(vector);
+    }
+    assert_eq!(Vector::RTTI.alignment, 64);
+    let children = unsafe { crate::verify::children_of(vectors[0].ptr.as_ptr()) };
+    assert_eq!(children.len(), 1);
+    assert_eq!(children[0].1, node.ptr.as_ptr());
+
+    let wide = immix_alloc_safe(u128::MAX);
+    assert_eq!(&*wide as *const u128 as usize % 16, 0);
+    assert_eq!(Gc::from_raw(&*wide as *const u128).ptr, wide.ptr);
+
+    immix_collect(true);
+    for (i, vector) in vectors.iter().enumerate() {
+        assert_eq!(&**vector as *const Vector as usize % 64, 0);
+        assert_eq!(vector.lanes[15], i as f32);
+        assert_eq!(vector.next.unwrap().value, 4);
+    }
+    assert_eq!(*wide, u128::MAX);
+    assert!(large_vector_aligned());
+    let header_size = core::mem::size_of::<RawGc>();
+    let raw = crate::immix_alloc_aligned(header_size + 256, 256, &ALIGNED_RTTI256);
+    assert!(!raw.is_null());
+    unsafe {
+        let offset = align_usize(header_size + 8, 256);
+        let value = raw.cast::<u8>().add(offset);
+        assert_eq!(value as usize % 256, 0);
+        assert_eq!(value.cast::<usize>().sub(1).read(), offset);
+        assert_eq!((*raw.cast::<RawGc>()).object_size(), offset + 256);
+    }
+    assert!(crate::immix_alloc_aligned(64, 48, &ALIGNED_RTTI256).is_null());
+}
+
+/// Allocate objects and return bitwise complements of their headers and addresses of their values, so only the
+/// values are visible to conservative stack scanning.
+#[inline(never)]
+fn alloc_values() -> ([usize; 6], [usize; 6]) {
+    let node = immix_alloc_safe(Node {
+        value: 1,
+        next: None,
+    });
+    let slice = crate::immix_alloc_slice(&[1u64, 2, 3]);
+    let string = crate::immix_alloc_str("value");
+    let callable: Gc<dyn Callable> = crate::immix_alloc_unsize(Adder { base: node });
+    let vector = immix_alloc_safe(Vector {
+        lanes: [1.0; 16],
+        next: None,
+    });
+    let medium = crate::immix_alloc_slice(&[7u64; 2048]);
+    let large = immix_alloc_safe(LargeVector { lanes: [1.0; 4096] });
+    (
+        [
+            !(slice.ptr.as_ptr() as usize),
+            !(string.ptr.as_ptr() as usize),
+            !(callable.ptr.as_ptr() as usize),
+            !(vector.ptr.as_ptr() as usize),
+            !(medium.ptr.as_ptr() as usize),
+            !(large.ptr.as_ptr() as usize),
+        ],
+        [
+            slice.as_ptr() as usize,
+            string.as_ptr() as usize,
+            &*callable as *const dyn Callable as *const u8 as usize,
+            &*vector as *const Vector as usize,
+            medium.as_ptr() as usize,
+            &*large as *const LargeVector as usize,
+        ],
+    )
+}
+
+/// Return true if object with header at bitwise complement `hidden` survived the last collection.
+fn survived(hidden: usize) -> bool {
+    let object = !hidden as *mut RawGc;
+    unsafe {
+        let space = &*crate::SPACE;
+        crate::collector::find_object(&*space.immix, &space.mos, &space.los, object.cast())
+            == Some(object)
+            && (*object).get_mark() == space.current_live_mark
+    }
+}
+
+#[test]
+fn references_to_values_keep_objects_alive() {
+    init();
+    let (headers, values) = alloc_values();
+    clobber_stack();
+    immix_collect(false);
+    std::hint::black_box(&values);
+    for &header in headers.iter() {
+        assert!(survived(header));
+    }
+}
+
+/// RTTI of 256 bytes value aligned to 256 bytes.
+static ALIGNED_RTTI256: GCRTTI = GCRTTI {
+    needs_finalization: false,
+    heap_size: {
+        extern "C" fn s(_: *mut u8) -> usize {
+            align_usize(core::mem::size_of::<RawGc>() + 8, 256) + 256
+        }
+        s
+    },
+    visit_references: crate::immix_noop_visit,
+    finalizer: None,
+    conservative: false,
+    alignment: 256,
+    notify_relocation: false,
+};
+
+/// Evacuate `object` as if its block was selected for evacuation.
+unsafe fn force_evacuate(object: *mut RawGc) -> *mut RawGc {
+    use crate::{block::ImmixBlock, util::Address};
+    let immix = &mut *(*crate::SPACE).immix;
+    if immix.evac_headroom() == 0 {
+        let free = (*immix.block_allocator).get_block().unwrap();
+        immix.extend_evac_headroom(Some(free));
+    }
+    let block = ImmixBlock::get_block_ptr(Address::from_ptr(object));
+    (*block).evacuation_candidate = true;
+    let moved = immix.maybe_evacuate(object);
+    (*block).evacuation_candidate = false;
+    moved
+        .expect("object was not evacuated")
+        .to_mut_ptr::<RawGc>()
+}
+
+#[test]
+fn identity_hash() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    init();
+    let node = immix_alloc_safe(Node {
+        value: 5,
+        next: None,
+    });
+    let other = immix_alloc_safe(Node {
+        value: 5,
+        next: None,
+    });
+    assert!(node == node.clone());
+    assert!(node != other);
+    let hash = crate::object::immix_identity_hash(unsafe { &*node.ptr.as_ptr().cast() });
+    assert_eq!(hash, node.ptr.as_ptr() as usize);
+    let size = unsafe { (*node.ptr.as_ptr()).object_size() };
+    unsafe {
+        let moved = force_evacuate(node.ptr.as_ptr());
+        assert_ne!(moved, node.ptr.as_ptr());
+        assert!((*moved).is_hashed_and_moved());
+        assert_eq!((*moved).identity_hash(), hash);
+        assert_eq!(
+            (*moved).object_size(),
+            align_usize((*moved).hash_slot_offset() + 8, 16)
+        );
+        assert!((*moved).object_size() >= size);
+        // the slot is copied when object is moved again
+        let moved_again = force_evacuate(moved);
+        assert_eq!((*moved_again).identity_hash(), hash);
+        let moved = Gc::<Node> {
+            ptr: core::ptr::NonNull::new_unchecked(moved_again),
+            marker: Default::default(),
+        };
+        assert_eq!(moved.value, 5);
+        let digest = |object: &Gc<Node>| {
+            let mut hasher = DefaultHasher::new();
+            object.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(digest(&moved), digest(&node));
+        // object that was not hashed does not get the slot
+        let unhashed = force_evacuate(other.ptr.as_ptr());
+        assert!(!(*unhashed).is_hashed());
+        assert_eq!((*unhashed).object_size(), size);
+    }
+}
+
+struct Interned {
+    id: usize,
+}
+
+impl HeapObject for Interned {
+    const RTTI: GCRTTI = GCRTTI {
+        notify_relocation: true,
+        ..make_rtti_for!(Interned)
+    };
+}
+
+static RELOCATIONS: std::sync::Mutex<Vec<(usize, usize)>> = std::sync::Mutex::new(Vec::new());
+
+extern "C" fn record_relocations(
+    data: *mut u8,
+    relocations: *const crate::relocation::Relocation,
+    count: usize,
+) {
+    assert_eq!(data as usize, 7);
+    let relocations = unsafe { std::slice::from_raw_parts(relocations, count) };
+    RELOCATIONS.lock().unwrap().extend(
+        relocations
+            .iter()
+            .map(|relocation| (relocation.old as usize, relocation.new as usize)),
+    );
+}
+
+#[test]
+fn relocation_notifications() {
+    init();
+    crate::relocation::immix_register_relocation_callback(record_relocations, 7 as *mut u8);
+    let interned = immix_alloc_safe(Interned { id: 1 });
+    let node = immix_alloc_safe(Node {
+        value: 2,
+        next: None,
+    });
+    let (moved, _) = unsafe {
+        (
+            force_evacuate(interned.ptr.as_ptr()),
+            force_evacuate(node.ptr.as_ptr()),
+        )
+    };
+    immix_collect(false);
+    // only objects with `notify_relocation` are reported, all moves of the cycle at once
+    assert_eq!(
+        *RELOCATIONS.lock().unwrap(),
+        vec![(interned.ptr.as_ptr() as usize, moved as usize)]
+    );
+    assert_eq!(interned.id, 1);
+    immix_collect(false);
+    assert_eq!(RELOCATIONS.lock().unwrap().len(), 1);
+}
+
+static EVACUATED: std::sync::Mutex<Vec<(usize, usize)>> = std::sync::Mutex::new(Vec::new());
+
+extern "C" fn record_evacuations(
+    _: *mut u8,
+    relocations: *const crate::relocation::Relocation,
+    count: usize,
+) {
+    let relocations = unsafe { std::slice::from_raw_parts(relocations, count) };
+    EVACUATED.lock().unwrap().extend(
+        relocations
+            .iter()
+            .map(|relocation| (relocation.old as usize, relocation.new as usize)),
+    );
+}
+
+/// Allocate array of interned objects with garbage between them so their blocks are fragmented, followed by
+/// garbage that fills whole blocks.
+#[inline(never)]
+fn alloc_fragmented_interned() -> Gc<crate::array::GcArray<Option<Gc<Interned>>>> {
+    let mut array = crate::array::GcArray::new(256, None);
+    for id in 0..256 {
+        for _ in 0..16 {
+            immix_alloc_safe(Node {
+                value: 0,
+                next: None,
+            });
+        }
+        unsafe { array.get_mut_unchecked()[id] = Some(immix_alloc_safe(Interned { id })) };
+    }
+    // blocks with only garbage become evacuation headroom
+    for _ in 0..8192 {
+        immix_alloc_safe(Node {
+            value: 0,
+            next: None,
+        });
+    }
+    array
+}
+
+#[test]
+fn relocation_notifications_from_evacuating_collection() {
+    init();
+    crate::relocation::immix_register_relocation_callback(record_evacuations, 0 as *mut u8);
+    // array itself is pinned by the stack, interned objects are reachable only from it
+    let array = alloc_fragmented_interned();
+    let old: Vec<usize> = array
+        .iter()
+        .map(|interned| interned.unwrap().ptr.as_ptr() as usize)
+        .collect();
+    clobber_stack();
+    // the first cycle finds holes left by garbage, the second one evacuates fragmented blocks
+    immix_collect(false);
+    immix_collect(true);
+    let evacuated = EVACUATED.lock().unwrap();
+    assert!(!evacuated.is_empty());
+    for &(from, to) in evacuated.iter() {
+        let id = old.iter().position(|&ptr| ptr == from).unwrap();
+        let interned = array[id].unwrap();
+        assert_eq!(interned.ptr.as_ptr() as usize, to);
+        assert_eq!(interned.id, id);
+    }
+}
+
+#[cfg(feature = "header-word")]
+#[test]
+fn header_word() {
+    use crate::object::*;
+    use core::sync::atomic::Ordering;
+    init();
+    let node = immix_alloc_safe(Node {
+        value: 1,
+        next: None,
+    });
+    assert_eq!(node.header_word().load(Ordering::Relaxed), 0);
+    node.header_word().store(0xcafe, Ordering::Relaxed);
+    let object = unsafe { &*(node.ptr.as_ptr() as *const crate::GCObject) };
+    assert_eq!(immix_header_word_load(object), 0xcafe);
+    assert_eq!(immix_header_word_fetch_or(object, 1), 0xcafe);
+    assert_eq!(immix_header_word_compare_exchange(object, 0, 7), 0xcaff);
+    assert_eq!(
+        immix_header_word_compare_exchange(object, 0xcaff, 7),
+        0xcaff
+    );
+    assert_eq!(immix_header_word_fetch_and(object, 3), 7);
+    immix_header_word_store(object, 42);
+    let vector = immix_alloc_safe(Vector {
+        lanes: [0.0; 16],
+        next: Some(node),
+    });
+    assert_eq!(&*vector as *const Vector as usize % 64, 0);
+    assert_eq!(Gc::from_raw(&*vector as *const Vector).ptr, vector.ptr);
+    assert_eq!(vector.header_word().load(Ordering::Relaxed), 0);
+    immix_collect(true);
+    assert_eq!(node.header_word().load(Ordering::Relaxed), 42);
+    assert_eq!(node.value, 1);
+}
+
+#[cfg(feature = "bdwgc-compat")]
+static FINALIZED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(feature = "bdwgc-compat")]
+#[inline(never)]
+fn bdwgc_alloc_finalizable() {
+    extern "C" fn fin(_: *mut libc::c_void, data: *mut libc::c_void) {
+        assert_eq!(data as usize, 42);
+        FINALIZED.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+    let p = crate::bdwgc::GC_malloc(32);
+    crate::bdwgc::GC_register_finalizer(
+        p,
+        Some(fin),
+        42 as *mut _,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+    );
+}
+
+#[cfg(feature = "bdwgc-compat")]
+#[inline(never)]
+fn bdwgc_check_large() {
+    for &size in &[16 * 1024, crate::constants::MEDIUM_OBJECT_LIMIT] {
+        let large = crate::bdwgc::GC_malloc(size);
+        assert_eq!(large as usize % 16, 0);
+        assert_eq!(crate::bdwgc::GC_size(large), size);
+    }
+}
+
+#[cfg(feature = "bdwgc-compat")]
+#[test]
+fn bdwgc_compat() {
+    use crate::bdwgc::*;
+    use core::sync::atomic::Ordering;
+    // BDWGC settings are applied even if GC was initialized with `immix_init` first.
+    init();
+    GC_init();
+    assert!(crate::collector::INTERIOR_POINTERS.load(Ordering::Acquire));
+    assert!(crate::static_roots::SCAN_STATIC_ROOTS.load(Ordering::Acquire));
+    let p = GC_malloc(24).cast::<usize>();
+    assert_eq!(p as usize % 16, 0);
+    assert_eq!(GC_size(p.cast()), 24);
+    unsafe {
+        assert_eq!(GC_base(p.add(2).cast()), p.cast());
+    }
+    bdwgc_check_large();
+    // heap size counts blocks in use, not the whole reservation
+    unsafe {
+        let reserved = (*(*(*crate::SPACE).immix).block_allocator).total_blocks()
+            * crate::constants::BLOCK_SIZE;
+        assert!(GC_get_heap_size() >= crate::constants::BLOCK_SIZE);
+        assert!(GC_get_heap_size() < reserved);
+    }
+
+    // atomic object is reachable only from uncollectable object
+    let u = GC_malloc_uncollectable(16).cast::<usize>();
+    unsafe {
+        let atomic = GC_malloc_atomic(100).cast::<u8>();
+        *atomic = 7;
+        *u = atomic as usize;
+    }
+    let hidden = std::hint::black_box(u as usize ^ usize::MAX);
+    bdwgc_alloc_finalizable();
+    // registering finalizer keeps pointer-free object unscanned
+    unsafe {
+        let atomic = GC_malloc_atomic(16);
+        extern "C" fn noop_fin(_: *mut libc::c_void, _: *mut libc::c_void) {}
+        GC_register_finalizer(
+            atomic,
+            Some(noop_fin),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        let object = crate::bdwgc::object_of(atomic).unwrap();
+        assert!(!(*object).rtti().conservative);
+        assert!((*object).rtti().needs_finalization);
+    }
+    clobber_stack();
+    GC_gcollect();
+    unsafe {
+        let u = (hidden ^ usize::MAX) as *mut usize;
+        assert!(!GC_base(*u as *mut _).is_null());
+        assert_eq!(*(*u as *const u8), 7);
+        let r = GC_realloc(u.cast(), 64).cast::<usize>();
+        assert_eq!(GC_size(r.cast()), 64);
+        assert_eq!(*(*r as *const u8), 7);
+    }
+    assert!(FINALIZED.load(std::sync::atomic::Ordering::SeqCst));
+}
@@ -16,8 +16,15 @@ mod sync {
         //pub alloc: *mut ThreadLocalAllocator,
         pub current_block: Option<BlockTuple>,
         pub current_ovf_block: Option<BlockTuple>,
+        /// Current block of allocator for objects without references.
+        pub current_atomic_block: Option<BlockTuple>,
+        /// Current block of overflow allocator for objects without references.
+        pub current_atomic_ovf_block: Option<BlockTuple>,
         pub stack_bottom: *mut u8,
         pub stack_end: *mut u8,
+        /// Bounds of static TLS block scanned for roots, null unless static roots are enabled.
+        pub tls_begin: *mut u8,
+        pub tls_end: *mut u8,
     }
     // gc_state = 1 means the thread is doing GC or is waiting for the GC to
     //              finish.
@@ -89,8 +96,12 @@ mod sync {
             gc_state: 0,
             current_block: None, //alloc: 0 as *mut _,
             current_ovf_block: None,
+            current_atomic_block: None,
+            current_atomic_ovf_block: None,
             stack_bottom: 0 as *mut _,
             stack_end: 0 as *mut _,
+            tls_begin: 0 as *mut _,
+            tls_end: 0 as *mut _,
         })
     };
     #[no_mangle]
@@ -155,9 +166,19 @@ mod sync {
         let threads = &*THREADS;
         let mut lock = threads.threads.lock();
         if immix_prepare_thread() {
+            if crate::static_roots::SCAN_STATIC_ROOTS.load(Ordering::Acquire) {
+                register_thread_local_roots();
+            }
             lock.push(immix_get_tls_state() as *mut _);
         }
     }
+    /// Register static TLS block of the current thread for scanning.
+    pub(crate) fn register_thread_local_roots() {
+        let (begin, end) = crate::static_roots::thread_local_block();
+        let ptls = immix_get_tls_state();
+        ptls.tls_begin = begin as *mut u8;
+        ptls.tls_end = end as *mut u8;
+    }
     /// Unregister thread.
     #[no_mangle]
     pub extern "C" fn immix_unregister_thread() {
@@ -222,6 +243,14 @@ mod unsync {
         /* no-op */
     }
 
+    /// Register static TLS block of the current thread for scanning.
+    pub(crate) fn register_thread_local_roots() {
+        let (begin, end) = crate::static_roots::thread_local_block();
+        unsafe {
+            (*crate::SPACE).add_roots(begin, end);
+        }
+    }
+
     /// Register thread.
     /// ## Inputs
     /// `sp`: pointer to variable on stack for searching roots on stack.
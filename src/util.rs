@@ -1,6 +1,6 @@
 #[macro_use]
 pub(crate) mod bitmap_const;
-use core::sync::atomic::{AtomicPtr, Ordering};
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
 use core::{
     alloc::Allocator,
     hash::{Hash, Hasher},
@@ -8,8 +8,8 @@ use core::{
     ptr::NonNull,
 };
 
-/// The mask to use for untagging a pointer.
-const UNTAG_MASK: usize = (!0x7) as usize;
+/// The mask to use for untagging a pointer. Three low bits and two high bits are used for tags.
+const UNTAG_MASK: u64 = !(0x7 | 0x3 << 62);
 
 /// Returns true if the pointer has the given bit set to 1.
 pub fn bit_is_set(pointer: u64, bit: usize) -> bool {
@@ -29,7 +29,7 @@ pub fn without_bit(pointer: u64, bit: usize) -> u64 {
 
 /// Returns the given pointer without any tags set.
 pub fn untagged<T>(pointer: u64) -> *mut T {
-    (pointer as u64 & UNTAG_MASK as u64) as _
+    (pointer & UNTAG_MASK) as _
 }
 
 /// Structure wrapping a raw, tagged pointer.
@@ -147,6 +147,12 @@ impl<T> TaggedPointer<T> {
         Self::new(self.atomic_load()).bit_is_set(bit)
     }
 
+    /// Atomically sets the given bit.
+    pub fn atomic_set_bit(&self, bit: usize) {
+        let raw = unsafe { &*(&self.raw as *const u64 as *const AtomicU64) };
+        raw.fetch_or(1 << bit as u64, Ordering::AcqRel);
+    }
+
     fn as_atomic(&self) -> &AtomicPtr<T> {
         unsafe { &*(self as *const TaggedPointer<T> as *const AtomicPtr<T>) }
     }
@@ -558,3 +564,74 @@ pub fn save_regs() {
     //__llvm_setjmp(buf.as_mut_ptr().cast());
     // }
 }
+
+/// Find symbol name of `address` (i.e RTTI of an object) using `dladdr`. Returns name without terminating zero.
+#[cfg(unix)]
+pub unsafe fn symbol_name(address: *const u8) -> Option<&'static [u8]> {
+    let mut info: libc::Dl_info = core::mem::zeroed();
+    if libc::dladdr(address.cast(), &mut info) == 0 || info.dli_sname.is_null() {
+        return None;
+    }
+    Some(core::slice::from_raw_parts(
+        info.dli_sname.cast::<u8>(),
+        libc::strlen(info.dli_sname),
+    ))
+}
+
+/// Buffered writer to a file opened with C `fopen`.
+pub(crate) struct FileWriter {
+    file: *mut libc::FILE,
+    buffer: alloc::vec::Vec<u8>,
+    failed: bool,
+}
+
+impl FileWriter {
+    /// Create or truncate file at `path`. Returns `None` if file could not be opened.
+    pub unsafe fn create(path: *const libc::c_char) -> Option<Self> {
+        let file = libc::fopen(path, b"wb\0".as_ptr().cast());
+        if file.is_null() {
+            return None;
+        }
+        Some(Self {
+            file,
+            buffer: alloc::vec::Vec::new(),
+            failed: false,
+        })
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() >= 64 * 1024 {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        unsafe {
+            if !self.buffer.is_empty()
+                && libc::fwrite(self.buffer.as_ptr().cast(), 1, self.buffer.len(), self.file)
+                    != self.buffer.len()
+            {
+                self.failed = true;
+            }
+        }
+        self.buffer.clear();
+    }
+
+    /// Flush and close the file. Returns false if any write failed.
+    pub fn finish(mut self) -> bool {
+        self.flush();
+        unsafe { libc::fclose(self.file) == 0 && !self.failed }
+    }
+}
+
+impl fmt::Write for FileWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        if self.failed {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
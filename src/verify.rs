@@ -0,0 +1,228 @@
+//! Heap verification.
+//!
+//! When enabled with [immix_enable_verification] heap is verified before and after each GC cycle.
+//! Every object reachable from roots is checked:
+//! - each reference traced by `visit_references` points to start of an object in Immix space
+//!   (i.e its bit is set in object bitmap) or to a large object;
+//! - after marking every line of live object in Immix space is marked in its block `LineMap`;
+//! - after evacuation no forwarded object is reachable.
+//!
+//! Failures are printed with addresses of offending objects and their RTTI and then process is aborted.
+use crate::{block::ImmixBlock, object::*, util::*, Immix};
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub(crate) static VERIFY: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable heap verification before and after each GC cycle. Verification is slow and should be used only
+/// for debugging `visit_references` implementations.
+#[no_mangle]
+pub extern "C" fn immix_enable_verification(enable: bool) {
+    VERIFY.store(enable, Ordering::Release);
+}
+
+/// Collects references of an object without modifying them.
+pub(crate) struct Children {
+    /// Slots and untagged values of references.
+    pub(crate) refs: Vec<(usize, *mut RawGc)>,
+}
+
+impl Children {
+    pub(crate) fn new() -> Self {
+        Self { refs: Vec::new() }
+    }
+
+    pub(crate) unsafe fn tracer(&mut self) -> TracerPtr {
+        TracerPtr {
+            tracer: core::mem::transmute(self as &mut dyn Tracer),
+        }
+    }
+}
+
+impl Tracer for Children {
+    fn trace(&mut self, reference: &mut NonNull<RawGc>) {
+        self.refs.push((
+            reference as *mut NonNull<RawGc> as usize,
+            reference.as_ptr(),
+        ));
+    }
+
+    fn trace_tagged(&mut self, slot: &mut usize, tag_mask: usize) {
+        let value = *slot & !tag_mask;
+        if value != 0 {
+            self.refs
+                .push((slot as *mut usize as usize, value as *mut RawGc));
+        }
+    }
+}
+
+/// Invoke `visit_references` of `object` and return slots and values of its references.
+pub(crate) unsafe fn children_of(object: *mut RawGc) -> Vec<(usize, *mut RawGc)> {
+    let mut children = Children::new();
+    ((*object).rtti().visit_references)(object.cast(), children.tracer());
+    children.refs
+}
+
+#[cfg(unix)]
+extern "C" {
+    fn printf(c: *const i8, ...) -> i32;
+}
+
+/// Print `object` and name of its RTTI if it can be found.
+#[cfg(unix)]
+unsafe fn print_object(object: *mut RawGc) {
+    let rtti = (*object).vtable();
+    let name = match symbol_name(rtti as *const u8) {
+        Some(name) => name,
+        None => b"<unknown>",
+    };
+    printf(
+        b"  object %p with RTTI %p (%.*s)\n\0".as_ptr().cast(),
+        object,
+        rtti as *const u8,
+        name.len() as i32,
+        name.as_ptr(),
+    );
+}
+
+struct Verifier<'a> {
+    immix: &'a Immix,
+    after: bool,
+    live_mark: bool,
+    failures: usize,
+}
+
+impl<'a> Verifier<'a> {
+    unsafe fn fail(&mut self, message: &[u8], object: *mut RawGc, slot: usize, value: *mut RawGc) {
+        self.failures += 1;
+        #[cfg(unix)]
+        {
+            printf(
+                b"Heap verification failed %s GC: %s\n\0".as_ptr().cast(),
+                if self.after {
+                    b"after\0".as_ptr()
+                } else {
+                    b"before\0".as_ptr()
+                },
+                message.as_ptr(),
+            );
+            if !object.is_null() {
+                print_object(object);
+            }
+            printf(
+                b"  reference at %p -> %p\n\0".as_ptr().cast(),
+                slot as *const u8,
+                value,
+            );
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (message, object, slot, value);
+        }
+    }
+
+    unsafe fn is_object(&self, object: *mut RawGc) -> bool {
+        (*self.immix.immix)
+            .filter(Address::from_ptr(object))
+            .is_some()
+            || self.immix.mos.contains(Address::from_ptr(object))
+            || self.immix.los.contains(Address::from_ptr(object))
+    }
+
+    /// Check reference stored at `slot` in `parent`. Returns true if reference can be followed.
+    unsafe fn check_reference(
+        &mut self,
+        parent: *mut RawGc,
+        slot: usize,
+        value: *mut RawGc,
+    ) -> bool {
+        if !self.is_object(value) {
+            self.fail(
+                b"reference does not point to an object\0",
+                parent,
+                slot,
+                value,
+            );
+            return false;
+        }
+        if self.after && (*value).is_forwarded() {
+            self.fail(b"reference to forwarded object\0", parent, slot, value);
+            return false;
+        }
+        true
+    }
+
+    unsafe fn check_object(&mut self, object: *mut RawGc) {
+        if !self.after {
+            return;
+        }
+        if (*object).get_mark() != self.live_mark {
+            self.fail(b"reachable object is not marked\0", object, 0, object);
+        }
+        let addr = Address::from_ptr(object);
+        if (*self.immix.immix).filter_fast(addr) {
+            let block = &*ImmixBlock::get_block_ptr(addr);
+            let first = ImmixBlock::object_to_line_num(addr);
+            let last = ImmixBlock::object_to_line_num(addr.offset((*object).object_size() - 1));
+            if (first..=last).any(|line| !block.line_is_marked(line)) {
+                self.fail(b"line of live object is not marked\0", object, 0, object);
+            }
+        }
+    }
+
+    unsafe fn verify(&mut self, roots: &[*mut RawGc], precise_roots: &[*mut *mut RawGc]) {
+        let mut visited = BTreeSet::new();
+        let mut queue = VecDeque::new();
+        for &root in roots.iter() {
+            queue.push_back(root);
+        }
+        for &slot in precise_roots.iter() {
+            let value = *slot;
+            if self.check_reference(core::ptr::null_mut(), slot as usize, value) {
+                queue.push_back(value);
+            }
+        }
+        while let Some(object) = queue.pop_front() {
+            if !visited.insert(object as usize) {
+                continue;
+            }
+            self.check_object(object);
+            if crate::collector::is_no_scan(&*self.immix.immix, &self.immix.mos, object) {
+                continue;
+            }
+            for (slot, child) in children_of(object) {
+                if self.check_reference(object, slot, child) {
+                    queue.push_back(child);
+                }
+            }
+        }
+    }
+}
+
+/// Verify heap reachable from `roots` and `precise_roots`. If `after` is true heap is verified after marking and
+/// all reachable objects must have `live_mark`.
+pub(crate) unsafe fn verify_heap(
+    immix: &Immix,
+    roots: &[*mut RawGc],
+    precise_roots: &[*mut *mut RawGc],
+    after: bool,
+    live_mark: bool,
+) {
+    let mut verifier = Verifier {
+        immix,
+        after,
+        live_mark,
+        failures: 0,
+    };
+    verifier.verify(roots, precise_roots);
+    if verifier.failures != 0 {
+        #[cfg(unix)]
+        printf(
+            b"Heap verification found %lu errors\n\0".as_ptr().cast(),
+            verifier.failures as u64,
+        );
+        libc::abort();
+    }
+}